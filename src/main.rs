@@ -7,12 +7,17 @@ use bevy::{
 mod pick;
 use pick::*;
 
+/// Pick layer for helpers like the rotation center, which shouldn't be picked by normal selection
+const GIZMO_LAYER: u8 = 1;
+
 #[derive(Default)]
 struct State {
     // Collects mouse motion in the form of an x/y delta Vec2
     mouse_motion_event_reader: EventReader<MouseMotion>,
     // Collects mouse scroll motion in x/y
     mouse_wheel_event_reader: EventReader<MouseWheel>,
+    // Collects double clicks on pickable meshes
+    double_click_event_reader: EventReader<PickDoubleClicked>,
 }
 
 fn main() {
@@ -24,6 +29,7 @@ fn main() {
         .add_plugin(PickingPlugin)
         .add_startup_system(setup.system())
         .add_system(process_user_input.system())
+        .add_system(set_rotation_center.system())
         .add_system(update_camera.system())
         //.add_system(cursor_pick.system())
         .run();
@@ -76,6 +82,10 @@ fn setup(
         .spawn(Camera3dComponents::default())
         .current_entity();
 
+    // The UI camera draws the drag selection rectangle, the picking plugin only spawns its own if
+    // there isn't one
+    commands.spawn(UiCameraComponents::default());
+
     let light_entity = commands
         .spawn(LightComponents {
             translation: Translation::new(0.0, 0.0, 5.0),
@@ -87,12 +97,13 @@ fn setup(
         })
         .current_entity();
 
+    let rotation_center_mesh = meshes.add(Mesh::from(shape::Icosphere {
+        radius: 0.1,
+        subdivisions: 1,
+    }));
     let rotation_center_entity = commands
         .spawn(PbrComponents {
-            mesh: meshes.add(Mesh::from(shape::Icosphere {
-                radius: 0.1,
-                subdivisions: 1,
-            })),
+            mesh: rotation_center_mesh,
             material: rotation_center_material_handle.clone(),
             translation: Translation::new(0.0, 0.0, 0.0),
             ..Default::default()
@@ -103,6 +114,9 @@ fn setup(
             cam_distance: 20.0,
             ..Default::default()
         })
+        // The rotation center is only pickable by tools that cast rays on the gizmo layer
+        .with(PickableMesh::new(meshes.get(&rotation_center_mesh).unwrap()))
+        .with(PickLayers::layer(GIZMO_LAYER))
         .current_entity();
 
     let cube_mesh = meshes.add(Mesh::from(shape::Cube { size: 1.0 }));
@@ -129,7 +143,15 @@ fn setup(
             ..Default::default()
         })
         .with(PickableMesh::new(meshes.get(&cube_mesh).unwrap()))
-        .with(HighlightablePickMesh::new())
+        // The cube is outlined instead of recolored when hovered
+        .with(HighlightablePickMesh::with_styles(HighlightStyles {
+            hovered: HighlightStyle::Outline {
+                color: Color::rgb(0.3, 0.5, 0.8),
+                width: 0.05,
+            },
+            ..Default::default()
+        }))
+        .with(SelectablePickMesh::new())
         .spawn(PbrComponents {
             mesh: sphere_mesh_1,
             material: geometry_material_handle.clone(),
@@ -137,6 +159,8 @@ fn setup(
             ..Default::default()
         })
         .with(PickableMesh::new(meshes.get(&sphere_mesh_1).unwrap()))
+        .with(HighlightablePickMesh::new())
+        .with(SelectablePickMesh::new())
         .spawn(PbrComponents {
             mesh: sphere_mesh_2,
             material: geometry_material_handle.clone(),
@@ -144,6 +168,8 @@ fn setup(
             ..Default::default()
         })
         .with(PickableMesh::new(meshes.get(&sphere_mesh_2).unwrap()))
+        .with(HighlightablePickMesh::new())
+        .with(SelectablePickMesh::new())
         //.with(LightIndicator {})
         // Create the environment.
         .spawn(LightComponents {
@@ -173,7 +199,7 @@ fn process_user_input(
     mouse_motion_events: Res<Events<MouseMotion>>,
     mouse_wheel_events: Res<Events<MouseWheel>>,
     keyboard_input: Res<Input<KeyCode>>,
-    pick_state: Res<PickState>,
+    mut selection_actions: ResMut<Events<PickSelectionAction>>,
     // Component Queries
     mut query: Query<&mut OrbitCamera>,
 ) {
@@ -199,10 +225,21 @@ fn process_user_input(
 
     let l_alt: bool = keyboard_input.pressed(KeyCode::LAlt);
     let l_shift: bool = keyboard_input.pressed(KeyCode::LShift);
+    let l_ctrl: bool = keyboard_input.pressed(KeyCode::LControl);
     //let l_mouse: bool = mouse_button_inputs.pressed(MouseButton::Left);
     let m_mouse: bool = mouse_button_inputs.pressed(MouseButton::Middle);
     //let r_mouse: bool = mouse_button_inputs.pressed(MouseButton::Right);
 
+    if l_ctrl && keyboard_input.just_pressed(KeyCode::A) {
+        selection_actions.send(PickSelectionAction::SelectAll);
+    } else if l_ctrl && keyboard_input.just_pressed(KeyCode::I) {
+        selection_actions.send(PickSelectionAction::Invert);
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+        selection_actions.send(PickSelectionAction::Clear);
+    } else if keyboard_input.just_pressed(KeyCode::Tab) {
+        selection_actions.send(PickSelectionAction::SelectOther);
+    }
+
     let manipulation = if l_alt && m_mouse {
         Some(CameraManipulation::Pan(mouse_movement))
     } else if l_shift && m_mouse {
@@ -219,8 +256,6 @@ fn process_user_input(
         match &manipulation {
             None => {}
             Some(CameraManipulation::Orbit(mouse_move)) => {
-                println!("PS: {:?}",pick_state.list());
-
                 camera.cam_yaw += mouse_move.delta.x() * time.delta_seconds;
                 camera.cam_pitch -= mouse_move.delta.y() * time.delta_seconds * look_scale;
             }
@@ -235,6 +270,25 @@ fn process_user_input(
     }
 }
 
+/// Move the rotation center to wherever the user double clicks on a mesh
+fn set_rotation_center(
+    // Resources
+    mut state: ResMut<State>,
+    double_click_events: Res<Events<PickDoubleClicked>>,
+    // Component Queries
+    mut query: Query<With<OrbitCamera, &mut Translation>>,
+) {
+    let mut rotation_center = None;
+    for event in state.double_click_event_reader.iter(&double_click_events) {
+        rotation_center = Some(event.hit.position());
+    }
+    if let Some(rotation_center) = rotation_center {
+        for mut translation in &mut query.iter() {
+            translation.0 = rotation_center;
+        }
+    }
+}
+
 fn update_camera(
     // Resources
     // Component Queries
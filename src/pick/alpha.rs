@@ -0,0 +1,184 @@
+use super::*;
+use bevy::render::texture::TextureFormat;
+
+/// Makes picking an entity alpha tested, so hits on the transparent parts of glass panels or decals
+/// are skipped, and picking continues to the meshes behind them. The alpha at a hit is the alpha of
+/// the material's albedo, multiplied by the alpha of its albedo texture at the hit's UV, if it has
+/// one. Primitives with an alpha below the cutoff at the hit are skipped, so the ray can still hit
+/// the entity's primitives behind them. To keep hits on an entity without letting it hide the
+/// meshes behind it, use `PickPassThrough` instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PickAlphaTest {
+    cutoff: f32,
+}
+
+impl PickAlphaTest {
+    pub fn new(cutoff: f32) -> Self {
+        PickAlphaTest { cutoff }
+    }
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff
+    }
+}
+
+impl Default for PickAlphaTest {
+    fn default() -> Self {
+        PickAlphaTest::new(0.5)
+    }
+}
+
+/// The material and texture assets `cast_ray` reads to alpha test the primitives it hits
+#[derive(Clone, Copy)]
+pub struct AlphaTestAssets<'a> {
+    pub materials: &'a Assets<StandardMaterial>,
+    pub textures: &'a Assets<Texture>,
+}
+
+/// The alpha test of an entity, with its material and UVs looked up once per ray cast, so each
+/// primitive the ray hits can be tested cheaply.
+pub(crate) struct AlphaTester<'a> {
+    cutoff: f32,
+    albedo_alpha: f32,
+    texture: Option<&'a Texture>,
+    uvs: Option<&'a [[f32; 2]]>,
+}
+
+impl<'a> AlphaTester<'a> {
+    /// Looks up the alpha test of an entity. Returns `None` if every hit on the entity passes,
+    /// because it has no `PickAlphaTest`, or because its material hasn't loaded yet. Highlighted
+    /// entities are tested against their initial material, not the material of their highlight.
+    pub(crate) fn new(
+        entity: Entity,
+        mesh: &'a Mesh,
+        assets: &AlphaTestAssets<'a>,
+        query: &Query<(&Handle<Mesh>, &Transform, &PickableMesh)>,
+    ) -> Option<Self> {
+        let alpha_test = *query.get::<PickAlphaTest>(entity).ok()?;
+        let material_handle = *query.get::<Handle<StandardMaterial>>(entity).ok()?;
+        let material_handle = match query.get::<HighlightablePickMesh>(entity) {
+            Ok(highlightable) => highlightable.initial_material().unwrap_or(material_handle),
+            Err(_) => material_handle,
+        };
+        let materials: &'a Assets<StandardMaterial> = assets.materials;
+        let textures: &'a Assets<Texture> = assets.textures;
+        let material = materials.get(&material_handle)?;
+        let uvs = mesh.attributes.iter()
+            .filter(|attribute| attribute.name == VertexAttribute::UV)
+            .filter_map(|attribute| match &attribute.values {
+                VertexAttributeValues::Float2(uvs) => Some(uvs.as_slice()),
+                _ => None,
+            }).last();
+        Some(AlphaTester {
+            cutoff: alpha_test.cutoff,
+            albedo_alpha: material.albedo.a,
+            texture: material.albedo_texture.and_then(|handle| textures.get(&handle)),
+            uvs,
+        })
+    }
+
+    /// Checks if a hit on a primitive passes the alpha test, given the vertex indices of the
+    /// primitive, and the barycentric coordinates of the hit. Hits are kept if the texture hasn't
+    /// loaded yet, or the mesh has no UVs to sample it with.
+    pub(crate) fn passes(&self, vertex_indices: &[u32], barycentric: Vec3) -> bool {
+        let texture_alpha = match (self.texture, self.uvs) {
+            (Some(texture), Some(uvs)) => hit_uv(uvs, vertex_indices, barycentric)
+                .and_then(|uv| texture_alpha(texture, uv))
+                .unwrap_or(1.0),
+            _ => 1.0,
+        };
+        self.albedo_alpha * texture_alpha >= self.cutoff
+    }
+}
+
+/// Interpolates the UV of a hit from the UVs of the vertices of the primitive that was hit, using
+/// the hit's barycentric coordinates.
+fn hit_uv(uvs: &[[f32; 2]], vertex_indices: &[u32], barycentric: Vec3) -> Option<Vec2> {
+    let weights = [barycentric.x(), barycentric.y(), barycentric.z()];
+    let mut uv = Vec2::zero();
+    for (index, weight) in vertex_indices.iter().zip(weights.iter()) {
+        // The UVs may not match the positions the primitives were decoded from, so they are bounds
+        // checked
+        uv += Vec2::from(*uvs.get(*index as usize)?) * *weight;
+    }
+    Some(uv)
+}
+
+/// Samples the alpha of the texel nearest to a UV, with UVs outside of 0 to 1 clamped to the edge
+/// of the texture. Returns `None` for texture formats without an 8 bit alpha channel.
+fn texture_alpha(texture: &Texture, uv: Vec2) -> Option<f32> {
+    match texture.format {
+        TextureFormat::Rgba8Unorm
+        | TextureFormat::Rgba8UnormSrgb
+        | TextureFormat::Bgra8Unorm
+        | TextureFormat::Bgra8UnormSrgb => {}
+        _ => return None,
+    }
+    let width = texture.size.x() as usize;
+    let height = texture.size.y() as usize;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let texel = |coordinate: f32, size: usize| {
+        ((coordinate.max(0.0).min(1.0) * size as f32) as usize).min(size - 1)
+    };
+    let (x, y) = (texel(uv.x(), width), texel(uv.y(), height));
+    // The alpha is the last of the four bytes of each texel
+    let alpha = *texture.data.get((y * width + x) * 4 + 3)?;
+    Some(alpha as f32 / 255.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alpha_test_skips_transparent_triangles() {
+        // Two triangles one behind the other. The front one samples the transparent left texel of
+        // the texture, and the back one samples the opaque right texel.
+        let triangle = |z: f32| vec![[0.0, 0.0, z], [1.0, 0.0, z], [0.0, 1.0, z]];
+        let uvs: Vec<[f32; 2]> = [[[0.0, 0.0]; 3], [[1.0, 0.0]; 3]].concat();
+        let mesh = Mesh {
+            primitive_topology: PrimitiveTopology::TriangleList,
+            attributes: vec![
+                VertexAttribute::position([triangle(0.0), triangle(1.0)].concat()),
+                VertexAttribute::uv(uvs.clone()),
+            ],
+            indices: None,
+        };
+        let texture = Texture::new(
+            Vec2::new(2.0, 1.0),
+            vec![0, 0, 0, 0, 0, 0, 0, 255],
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        let alpha_tester = AlphaTester {
+            cutoff: 0.5,
+            albedo_alpha: 1.0,
+            texture: Some(&texture),
+            uvs: Some(&uvs[..]),
+        };
+        let entity = test_entities(1)[0];
+        let ray = Ray3d::new(Vec3::new(0.25, 0.25, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let tolerance = PickTolerance::none();
+        let cached = CachedMeshPrimitives::new(&mesh);
+        // With and without a BVH
+        for primitives in [None, cached.as_ref()].iter() {
+            let cast = |alpha_tester: Option<&AlphaTester>| {
+                let mesh_to_world = Mat4::identity();
+                ray_mesh_intersection(
+                    &ray,
+                    &tolerance,
+                    &mesh,
+                    *primitives,
+                    alpha_tester,
+                    &mesh_to_world,
+                    entity,
+                )
+                .unwrap()
+            };
+            assert_eq!(cast(None).primitive_index, 0);
+            let hit = cast(Some(&alpha_tester));
+            assert_eq!(hit.primitive_index, 1);
+            assert!((hit.distance - 2.0).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,505 @@
+use super::*;
+use std::collections::HashMap;
+
+/// Nodes with this many triangles or fewer are not split any further
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+/// An axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Aabb {
+    pub(crate) min: Vec3,
+    pub(crate) max: Vec3,
+}
+
+impl Aabb {
+    /// A box containing nothing, which can be grown to fit points
+    pub(crate) fn empty() -> Self {
+        Aabb {
+            min: Vec3::splat(f32::MAX),
+            max: Vec3::splat(f32::MIN),
+        }
+    }
+
+    pub(crate) fn grow(&mut self, point: Vec3) {
+        self.min = self.min.min(point);
+        self.max = self.max.max(point);
+    }
+
+    /// The smallest box containing both boxes
+    pub(crate) fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub(crate) fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.0
+    }
+
+    pub(crate) fn contains(&self, other: &Aabb) -> bool {
+        self.min.cmple(other.min).all() && self.max.cmpge(other.max).all()
+    }
+
+    pub(crate) fn surface_area(&self) -> f32 {
+        let extent = self.max - self.min;
+        2.0 * (extent.x() * extent.y() + extent.y() * extent.z() + extent.z() * extent.x())
+    }
+
+    /// Checks if a ray hits the box using the slab method, and returns the distance along the ray
+    /// where it enters the box, or zero if the ray starts inside it. `inverse_direction` is the
+    /// reciprocal of each component of the ray direction, computed once per ray.
+    pub(crate) fn ray_entry_distance(&self, ray: &Ray3d, inverse_direction: Vec3) -> Option<f32> {
+        // The distances along the ray where it enters and exits the slab between two planes
+        let slab = |origin: f32, inverse_direction: f32, min: f32, max: f32| {
+            if inverse_direction.is_infinite() {
+                // The ray is parallel to the slab, so it is either always or never inside it. This
+                // is checked directly, because a ray starting on one of the planes would give
+                // 0 * inf = NaN distances.
+                if origin < min || origin > max {
+                    None
+                } else {
+                    Some((f32::MIN, f32::MAX))
+                }
+            } else {
+                let t_1 = (min - origin) * inverse_direction;
+                let t_2 = (max - origin) * inverse_direction;
+                Some((t_1.min(t_2), t_1.max(t_2)))
+            }
+        };
+        let origin = ray.origin();
+        let (enter_x, exit_x) =
+            slab(origin.x(), inverse_direction.x(), self.min.x(), self.max.x())?;
+        let (enter_y, exit_y) =
+            slab(origin.y(), inverse_direction.y(), self.min.y(), self.max.y())?;
+        let (enter_z, exit_z) =
+            slab(origin.z(), inverse_direction.z(), self.min.z(), self.max.z())?;
+        let t_enter = enter_x.max(enter_y).max(enter_z);
+        let t_exit = exit_x.min(exit_y).min(exit_z);
+        if t_exit >= t_enter.max(0.0) {
+            Some(t_enter.max(0.0))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+enum BvhNodeKind {
+    /// Holds `count` triangles, starting at `start` in `MeshBvh::triangles`
+    Leaf { start: usize, count: usize },
+    /// Holds the indices of two child nodes in `MeshBvh::nodes`
+    Branch { left: usize, right: usize },
+}
+
+#[derive(Debug)]
+struct BvhNode {
+    aabb: Aabb,
+    kind: BvhNodeKind,
+}
+
+/// A bounding volume hierarchy over the triangles of a mesh, in the mesh's coordinate system. Rays
+/// only need to be tested against the triangles in the boxes they pass through, instead of every
+/// triangle in the mesh. The BVH keeps everything needed to describe a hit, so ray casts against
+/// it never read the mesh's attributes.
+#[derive(Debug)]
+pub(crate) struct MeshBvh {
+    nodes: Vec<BvhNode>,
+    // Indices of the triangles in the mesh, ordered so that the triangles of each leaf are together
+    triangles: Vec<usize>,
+    // Vertex positions of each triangle, in the order the triangles appear in the mesh
+    vertices: Vec<[Vec3; 3]>,
+    // Vertex indices of each triangle
+    indices: Vec<[u32; 3]>,
+    // Vertex normals of each triangle, if the mesh has them
+    normals: Option<Vec<[Vec3; 3]>>,
+}
+
+impl MeshBvh {
+    /// Builds a BVH for the decoded triangles of a `TriangleList` or `TriangleStrip` mesh, with the
+    /// vertex indices already validated
+    fn from_triangles(
+        vertex_positions: &[[f32; 3]],
+        triangles: Vec<[u32; 3]>,
+        normals: Option<Vec<[f32; 3]>>,
+    ) -> Self {
+        let vertices: Vec<[Vec3; 3]> = triangles
+            .iter()
+            .map(|index| {
+                [
+                    Vec3::from(vertex_positions[index[0] as usize]),
+                    Vec3::from(vertex_positions[index[1] as usize]),
+                    Vec3::from(vertex_positions[index[2] as usize]),
+                ]
+            })
+            .collect();
+        let normals = normals.map(|normals| {
+            triangles
+                .iter()
+                .map(|index| {
+                    [
+                        Vec3::from(normals[index[0] as usize]),
+                        Vec3::from(normals[index[1] as usize]),
+                        Vec3::from(normals[index[2] as usize]),
+                    ]
+                })
+                .collect()
+        });
+
+        let mut bvh = MeshBvh {
+            nodes: Vec::new(),
+            triangles: (0..vertices.len()).collect(),
+            vertices,
+            indices: triangles,
+            normals,
+        };
+        if !bvh.triangles.is_empty() {
+            let centers: Vec<Vec3> = bvh
+                .vertices
+                .iter()
+                .map(|triangle| (triangle[0] + triangle[1] + triangle[2]) / 3.0)
+                .collect();
+            bvh.build_node(0, bvh.triangles.len(), &centers);
+        }
+        bvh
+    }
+
+    /// Recursively builds the node containing `count` triangles starting at `start`, and returns
+    /// its index. Nodes are split in half at the median triangle along their longest axis.
+    fn build_node(&mut self, start: usize, count: usize, centers: &[Vec3]) -> usize {
+        let mut aabb = Aabb::empty();
+        for triangle in &self.triangles[start..start + count] {
+            for vertex in self.vertices[*triangle].iter() {
+                aabb.grow(*vertex);
+            }
+        }
+        let node_index = self.nodes.len();
+        self.nodes.push(BvhNode {
+            aabb,
+            kind: BvhNodeKind::Leaf { start, count },
+        });
+        if count <= MAX_LEAF_TRIANGLES {
+            return node_index;
+        }
+
+        let extent = aabb.max - aabb.min;
+        let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() {
+            0
+        } else if extent.y() >= extent.z() {
+            1
+        } else {
+            2
+        };
+        let axis_value = |center: &Vec3| match axis {
+            0 => center.x(),
+            1 => center.y(),
+            _ => center.z(),
+        };
+        self.triangles[start..start + count].sort_by(|a, b| {
+            axis_value(&centers[*a])
+                .partial_cmp(&axis_value(&centers[*b]))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let left_count = count / 2;
+        let left = self.build_node(start, left_count, centers);
+        let right = self.build_node(start + left_count, count - left_count, centers);
+        self.nodes[node_index].kind = BvhNodeKind::Branch { left, right };
+        node_index
+    }
+
+    /// The normal of a triangle at a hit, in the mesh's coordinate system. This is interpolated
+    /// from the vertex normals if the mesh has them, otherwise it is the triangle's face normal.
+    /// The normal is not normalized.
+    pub(crate) fn normal(&self, triangle_index: usize, barycentric: Vec3) -> Vec3 {
+        match &self.normals {
+            Some(normals) => {
+                let normal = normals[triangle_index];
+                normal[0] * barycentric.x()
+                    + normal[1] * barycentric.y()
+                    + normal[2] * barycentric.z()
+            }
+            None => {
+                let [a, b, c] = self.vertices[triangle_index];
+                (b - a).cross(c - a)
+            }
+        }
+    }
+
+    /// Finds the closest triangle hit by a ray in the mesh's coordinate system, skipping triangles
+    /// that fail the alpha test. Returns the distance along the ray, the index of the triangle in
+    /// the mesh, and the barycentric coordinates of the hit.
+    pub(crate) fn intersect(
+        &self,
+        ray: &Ray3d,
+        alpha_tester: Option<&AlphaTester>,
+    ) -> Option<(f32, usize, Vec3)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inverse_direction = Vec3::one() / ray.direction();
+        let mut closest_hit: Option<(f32, usize, Vec3)> = None;
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            // Skip nodes that the ray misses, or that are further away than the closest hit
+            match node.aabb.ray_entry_distance(ray, inverse_direction) {
+                Some(distance) if closest_hit.map_or(true, |(closest, ..)| distance < closest) => {}
+                _ => continue,
+            }
+            match node.kind {
+                BvhNodeKind::Leaf { start, count } => {
+                    for triangle in &self.triangles[start..start + count] {
+                        if let Some((distance, barycentric)) =
+                            ray_triangle_intersection(ray, &self.vertices[*triangle])
+                        {
+                            let passes = |tester: &AlphaTester| {
+                                tester.passes(&self.indices[*triangle], barycentric)
+                            };
+                            if closest_hit.map_or(true, |(closest, ..)| distance < closest)
+                                && alpha_tester.map_or(true, passes)
+                            {
+                                closest_hit = Some((distance, *triangle, barycentric));
+                            }
+                        }
+                    }
+                }
+                BvhNodeKind::Branch { left, right } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+        closest_hit
+    }
+}
+
+/// The primitives of a mesh, decoded once so ray casts don't need to read the mesh's attributes
+#[derive(Debug)]
+pub(crate) enum CachedMeshPrimitives {
+    /// Triangles are kept in a BVH, so rays only need to be tested against a few of them
+    Triangles(MeshBvh),
+    /// Vertex positions in the mesh's coordinate system, and the vertex indices of each line
+    Lines {
+        positions: Vec<Vec3>,
+        lines: Vec<[u32; 2]>,
+    },
+    /// Vertex positions in the mesh's coordinate system, and the vertex index of each point
+    Points {
+        positions: Vec<Vec3>,
+        points: Vec<u32>,
+    },
+}
+
+impl CachedMeshPrimitives {
+    /// Decodes the primitives of a mesh, returns `None` for invalid meshes
+    pub(crate) fn new(mesh: &Mesh) -> Option<Self> {
+        let vertex_positions = mesh_vertex_positions(mesh).ok()?;
+        let positions = || vertex_positions.iter().copied().map(Vec3::from).collect();
+        Some(match mesh_primitives(mesh, vertex_positions.len()).ok()? {
+            MeshPrimitives::Triangles(triangles) => {
+                let normals = mesh_vertex_normals(mesh, vertex_positions.len());
+                CachedMeshPrimitives::Triangles(MeshBvh::from_triangles(
+                    &vertex_positions,
+                    triangles,
+                    normals,
+                ))
+            }
+            MeshPrimitives::Lines(lines) => CachedMeshPrimitives::Lines {
+                positions: positions(),
+                lines,
+            },
+            MeshPrimitives::Points(points) => CachedMeshPrimitives::Points {
+                positions: positions(),
+                points,
+            },
+        })
+    }
+}
+
+/// Caches the decoded primitives of each mesh used by a `PickableMesh`, with a BVH for triangle
+/// meshes, so meshes shared by many entities only need to be processed once, and ray casts don't
+/// allocate for every mesh they test.
+#[derive(Default)]
+pub struct PickBvhCache {
+    primitives: HashMap<Handle<Mesh>, CachedMeshPrimitives>,
+    mesh_event_reader: EventReader<AssetEvent<Mesh>>,
+}
+
+impl PickBvhCache {
+    pub(crate) fn get(&self, mesh_handle: &Handle<Mesh>) -> Option<&CachedMeshPrimitives> {
+        self.primitives.get(mesh_handle)
+    }
+}
+
+/// Caches the primitives of the meshes of newly added `PickableMesh` entities, and rebuilds or
+/// drops cached primitives when their mesh asset is modified or removed. Meshes that haven't loaded
+/// yet are cached once their asset is created.
+pub(crate) fn update_mesh_bvhs(
+    // Resources
+    mut bvh_cache: ResMut<PickBvhCache>,
+    mesh_events: Res<Events<AssetEvent<Mesh>>>,
+    meshes: Res<Assets<Mesh>>,
+    // Queries
+    mut added_query: Query<With<Added<'static, PickableMesh>, &Handle<Mesh>>>,
+    mut changed_mesh_query: Query<With<PickableMesh, Changed<Handle<Mesh>>>>,
+    mut pickable_query: Query<With<PickableMesh, &Handle<Mesh>>>,
+) {
+    let mut stale_meshes = Vec::new();
+    for event in bvh_cache.mesh_event_reader.iter(&mesh_events) {
+        match event {
+            AssetEvent::Created { handle }
+            | AssetEvent::Modified { handle }
+            | AssetEvent::Removed { handle } => stale_meshes.push(*handle),
+        }
+    }
+    for mesh_handle in stale_meshes.iter() {
+        bvh_cache.primitives.remove(mesh_handle);
+    }
+
+    let mut wanted_meshes = Vec::new();
+    for mesh_handle in &mut added_query.iter() {
+        wanted_meshes.push(*mesh_handle);
+    }
+    for mesh_handle in &mut changed_mesh_query.iter() {
+        wanted_meshes.push(*mesh_handle);
+    }
+    if !stale_meshes.is_empty() {
+        for mesh_handle in &mut pickable_query.iter() {
+            if stale_meshes.contains(mesh_handle) {
+                wanted_meshes.push(*mesh_handle);
+            }
+        }
+    }
+
+    for mesh_handle in wanted_meshes {
+        if bvh_cache.primitives.contains_key(&mesh_handle) {
+            continue;
+        }
+        if let Some(primitives) = meshes.get(&mesh_handle).and_then(CachedMeshPrimitives::new) {
+            bvh_cache.primitives.insert(mesh_handle, primitives);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle_bvh(mesh: &Mesh) -> MeshBvh {
+        match CachedMeshPrimitives::new(mesh) {
+            Some(CachedMeshPrimitives::Triangles(bvh)) => bvh,
+            primitives => panic!("Expected a BVH, got {:?}", primitives),
+        }
+    }
+
+    /// Tests every triangle of the mesh, the same as picking does without a BVH
+    fn brute_force(mesh: &Mesh, ray: &Ray3d) -> Option<PickDepth> {
+        let entity = test_entities(1)[0];
+        let tolerance = PickTolerance::none();
+        ray_mesh_intersection(ray, &tolerance, mesh, None, None, &Mat4::identity(), entity)
+    }
+
+    /// Checks that the BVH finds the same hit as testing every triangle
+    fn assert_matches_brute_force(mesh: &Mesh, bvh: &MeshBvh, ray: &Ray3d) -> bool {
+        match (brute_force(mesh, ray), bvh.intersect(ray, None)) {
+            (Some(expected), Some((actual, triangle_index, barycentric))) => {
+                let expected = expected.distance;
+                assert!((expected - actual).abs() < 1e-5, "{} != {}", expected, actual);
+                // The hit triangle may differ on a shared edge, but must be as close
+                let [a, b, c] = bvh.vertices[triangle_index];
+                let position = a * barycentric.x() + b * barycentric.y() + c * barycentric.z();
+                assert!((position - ray.position(actual)).length() < 1e-4);
+                true
+            }
+            (None, None) => false,
+            (expected, actual) => panic!("{:?} != {:?}", expected, actual),
+        }
+    }
+
+    #[test]
+    fn bvh_matches_brute_force() {
+        let mesh = Mesh::from(shape::Icosphere {
+            radius: 1.0,
+            subdivisions: 3,
+        });
+        let bvh = triangle_bvh(&mesh);
+        let mut hits = 0;
+        // A grid of rays from every side of the sphere, including some that miss it and some that
+        // start inside it
+        for i in 0..21 {
+            for j in 0..21 {
+                let x = i as f32 * 0.13 - 1.31;
+                let y = j as f32 * 0.13 - 1.29;
+                let rays = [
+                    Ray3d::new(Vec3::new(x, y, -3.0), Vec3::new(0.0, 0.0, 1.0)),
+                    Ray3d::new(Vec3::new(3.0, x, y), Vec3::new(-1.0, 0.1, 0.2)),
+                    Ray3d::new(Vec3::new(x * 0.5, y * 0.5, 0.1), Vec3::new(0.3, -0.7, 0.5)),
+                ];
+                for ray in rays.iter() {
+                    if assert_matches_brute_force(&mesh, &bvh, ray) {
+                        hits += 1;
+                    }
+                }
+            }
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn ray_on_box_face() {
+        let aabb = Aabb {
+            min: Vec3::zero(),
+            max: Vec3::one(),
+        };
+        // Rays parallel to the x and y axes, starting in the box's x = 0 face
+        let ray = Ray3d::new(Vec3::new(0.0, 0.5, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let inverse_direction = Vec3::one() / ray.direction();
+        assert_eq!(aabb.ray_entry_distance(&ray, inverse_direction), Some(1.0));
+        let ray = Ray3d::new(Vec3::new(0.0, 0.5, 0.5), Vec3::new(0.0, 1.0, 0.0));
+        let inverse_direction = Vec3::one() / ray.direction();
+        assert_eq!(aabb.ray_entry_distance(&ray, inverse_direction), Some(0.0));
+        let ray = Ray3d::new(Vec3::new(-0.1, 0.5, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let inverse_direction = Vec3::one() / ray.direction();
+        assert_eq!(aabb.ray_entry_distance(&ray, inverse_direction), None);
+    }
+
+    #[test]
+    fn bvh_axis_aligned_rays_through_x_0() {
+        // The icosphere has vertices and edges on the x = 0 plane, so some of the BVH's boxes
+        // have a face on it
+        let mesh = Mesh::from(shape::Icosphere {
+            radius: 1.0,
+            subdivisions: 3,
+        });
+        let bvh = triangle_bvh(&mesh);
+        let mut hits = 0;
+        for i in 0..21 {
+            let y = i as f32 * 0.1 - 1.0;
+            let rays = [
+                Ray3d::new(Vec3::new(0.0, y, -3.0), Vec3::new(0.0, 0.0, 1.0)),
+                Ray3d::new(Vec3::new(0.0, -3.0, y), Vec3::new(0.0, 1.0, 0.0)),
+            ];
+            for ray in rays.iter() {
+                if assert_matches_brute_force(&mesh, &bvh, ray) {
+                    hits += 1;
+                }
+            }
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn bvh_normal_is_interpolated() {
+        let mesh = Mesh::from(shape::Icosphere {
+            radius: 1.0,
+            subdivisions: 2,
+        });
+        let bvh = triangle_bvh(&mesh);
+        let ray = Ray3d::new(Vec3::new(0.1, 0.2, -3.0), Vec3::new(0.0, 0.0, 1.0));
+        let (distance, triangle_index, barycentric) = bvh.intersect(&ray, None).unwrap();
+        // The sphere's vertex normals point away from its center
+        let normal = bvh.normal(triangle_index, barycentric).normalize();
+        assert!(normal.dot(ray.position(distance).normalize()) > 0.99);
+    }
+}
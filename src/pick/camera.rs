@@ -0,0 +1,117 @@
+use super::*;
+
+/// Marks a camera as a source of picking rays. If any camera has this component, only those
+/// cameras are used for picking. Otherwise every camera except the UI camera is.
+#[derive(Debug, Default)]
+pub struct PickSource;
+
+/// The part of its window that a camera renders to, used to pick with split screen cameras. The
+/// corners are fractions of the window size, from (0, 0) at the bottom left to (1, 1) at the top
+/// right. Cameras without a `PickViewport` cover their whole window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PickViewport {
+    min: Vec2,
+    max: Vec2,
+}
+
+impl PickViewport {
+    pub fn new(min: Vec2, max: Vec2) -> Self {
+        PickViewport {
+            min: min.min(max),
+            max: min.max(max),
+        }
+    }
+    pub fn min(&self) -> Vec2 {
+        self.min
+    }
+    pub fn max(&self) -> Vec2 {
+        self.max
+    }
+}
+
+impl Default for PickViewport {
+    fn default() -> Self {
+        PickViewport::new(Vec2::zero(), Vec2::one())
+    }
+}
+
+/// A camera chosen for picking, with its viewport in window pixels
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PickCamera {
+    pub(crate) transform: Mat4,
+    pub(crate) projection_matrix: Mat4,
+    viewport_min: Vec2,
+    viewport_size: Vec2,
+}
+
+impl PickCamera {
+    /// The combined view and projection matrix of the camera, used to transform world space
+    /// positions into clip space.
+    pub(crate) fn view_projection(&self) -> Mat4 {
+        self.projection_matrix * self.transform.inverse()
+    }
+
+    pub(crate) fn viewport_size(&self) -> Vec2 {
+        self.viewport_size
+    }
+
+    /// Converts a position in window pixels to the camera's normalized device coordinates, which
+    /// span from (-1, -1) to (1, 1) across its viewport. Cursor positions and window sizes are both
+    /// in physical pixels, so this is independent of the window's scale factor.
+    pub(crate) fn screen_to_ndc(&self, position: Vec2) -> Vec2 {
+        ((position - self.viewport_min) / self.viewport_size) * 2.0 - Vec2::one()
+    }
+
+    fn contains(&self, position: Vec2) -> bool {
+        let max = self.viewport_min + self.viewport_size;
+        position.cmpge(self.viewport_min).all() && position.cmple(max).all()
+    }
+}
+
+/// Finds the camera whose viewport contains a position in a window. Only `PickSource` cameras are
+/// considered if there are any, and the UI camera is ignored, it only draws the UI overlay. If
+/// viewports overlap, the last camera found is used.
+pub(crate) fn camera_at_position(
+    window_id: WindowId,
+    position: Vec2,
+    windows: &Windows,
+    camera_query: &mut Query<(&Transform, &Camera, Entity)>,
+) -> Option<PickCamera> {
+    // The window size is read every time, so viewports follow the window when it is resized
+    let window = windows.get(window_id)?;
+    let window_size = Vec2::new(window.width as f32, window.height as f32);
+    let mut cameras = Vec::new();
+    for (transform, camera, entity) in &mut camera_query.iter() {
+        if camera.name.as_deref() == Some(UI_CAMERA) {
+            continue;
+        }
+        cameras.push((transform.value, camera.projection_matrix, camera.window, entity));
+    }
+    let has_sources = cameras
+        .iter()
+        .any(|(.., entity)| camera_query.get::<PickSource>(*entity).is_ok());
+
+    let mut pick_camera = None;
+    for (transform, projection_matrix, camera_window, entity) in cameras {
+        if camera_window != window_id {
+            continue;
+        }
+        if has_sources && camera_query.get::<PickSource>(entity).is_err() {
+            continue;
+        }
+        let viewport = match camera_query.get::<PickViewport>(entity) {
+            Ok(viewport) => *viewport,
+            Err(_) => PickViewport::default(),
+        };
+        let candidate = PickCamera {
+            transform,
+            projection_matrix,
+            viewport_min: viewport.min * window_size,
+            viewport_size: (viewport.max - viewport.min) * window_size,
+        };
+        if candidate.contains(position) {
+            pick_camera = Some(candidate);
+        }
+    }
+    pick_camera
+}
@@ -0,0 +1,563 @@
+use super::*;
+use bevy::input::keyboard::KeyCode;
+
+/// Determines which meshes are selected by a drag rectangle or lasso
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoxSelectMode {
+    /// Only meshes entirely inside the region are selected
+    Window,
+    /// Any mesh inside or touching the region is selected
+    Crossing,
+    /// Dragging a rectangle left to right uses `Window`, dragging right to left uses `Crossing`.
+    /// Lassos have no drag direction, and always use `Window`.
+    ByDragDirection,
+}
+
+/// The shape drawn by the cursor during a drag selection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DragSelectShape {
+    /// A rectangle spanning from where the drag started to the cursor
+    Box,
+    /// A freeform polygon following the path of the cursor
+    Lasso,
+}
+
+/// Configures drag selection. The rectangle and lasso are drawn as UI nodes, so they need a UI
+/// camera, `PickingPlugin` spawns one if the app doesn't have one on its first frame.
+#[derive(Debug)]
+pub struct DragSelectParams {
+    mode: BoxSelectMode,
+    // Drags started while this key is held draw a lasso instead of a box
+    lasso_key: KeyCode,
+    // Minimum distance in pixels between consecutive lasso points
+    lasso_spacing: f32,
+    rect_color: Color,
+    lasso_color: Color,
+}
+
+impl DragSelectParams {
+    pub fn set_mode(&mut self, mode: BoxSelectMode) {
+        self.mode = mode;
+    }
+    pub fn mode(&self) -> BoxSelectMode {
+        self.mode
+    }
+    pub fn set_lasso_key(&mut self, key: KeyCode) {
+        self.lasso_key = key;
+    }
+}
+
+impl Default for DragSelectParams {
+    fn default() -> Self {
+        DragSelectParams {
+            mode: BoxSelectMode::ByDragDirection,
+            lasso_key: KeyCode::LAlt,
+            lasso_spacing: 4.0,
+            rect_color: Color::rgba(0.3, 0.5, 0.8, 0.25),
+            lasso_color: Color::rgb(0.3, 0.5, 0.8),
+        }
+    }
+}
+
+/// Tracks an in-progress drag selection
+#[derive(Debug, Default)]
+pub struct DragSelectState {
+    // Cursor position when the left mouse button was pressed
+    start: Option<Vec2>,
+    // Set while the press counts as a drag, as decided by `pick_events`
+    dragging: bool,
+    shape: Option<DragSelectShape>,
+    // Cursor path of the lasso in screen space
+    lasso_points: Vec<Vec2>,
+    lasso_material: Handle<ColorMaterial>,
+    // Whether the app has been checked for a UI camera
+    ui_camera_checked: bool,
+}
+
+impl DragSelectState {
+    pub fn is_dragging(&self) -> bool {
+        self.dragging
+    }
+    pub fn shape(&self) -> Option<DragSelectShape> {
+        self.shape
+    }
+}
+
+/// Marks the UI node used to draw the drag selection rectangle
+struct DragSelectRect;
+
+/// Marks the UI nodes used to draw the points along the lasso path
+struct DragSelectLassoPoint;
+
+/// Spawns the (initially hidden) UI node used to draw the drag selection rectangle
+pub(crate) fn drag_select_init(
+    // Commands
+    mut commands: Commands,
+    // Resources
+    params: Res<DragSelectParams>,
+    mut drag_state: ResMut<DragSelectState>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+    drag_state.lasso_material = color_materials.add(params.lasso_color.into());
+    commands
+        .spawn(NodeComponents {
+            style: Style {
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            },
+            material: color_materials.add(params.rect_color.into()),
+            draw: Draw {
+                is_visible: false,
+                is_transparent: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .with(DragSelectRect);
+}
+
+/// Spawns a UI camera on the first frame if the app doesn't have one, otherwise the drag selection
+/// rectangle, lasso, and select other list are never drawn. This isn't done at startup, because
+/// the app's own startup systems may not have spawned their cameras yet.
+pub(crate) fn spawn_ui_camera(
+    // Commands
+    mut commands: Commands,
+    // Resources
+    mut drag_state: ResMut<DragSelectState>,
+    // Queries
+    mut camera_query: Query<&Camera>,
+) {
+    if drag_state.ui_camera_checked {
+        return;
+    }
+    drag_state.ui_camera_checked = true;
+    for camera in &mut camera_query.iter() {
+        if camera.name.as_deref() == Some(UI_CAMERA) {
+            return;
+        }
+    }
+    commands.spawn(UiCameraComponents::default());
+}
+
+/// Tracks left mouse drags, draws the selection rectangle or lasso while dragging, and on release
+/// selects every `SelectablePickMesh` inside or touching the region, depending on the
+/// `BoxSelectMode`. Modifier keys behave the same as for click selection. Whether a press is a
+/// drag is decided by `pick_events`, and regions smaller than the drag threshold are ignored.
+pub(crate) fn drag_select(
+    // Commands
+    mut commands: Commands,
+    // Resources
+    pick_state: Res<PickState>,
+    event_state: Res<PickEventState>,
+    picking_params: Res<PickingParams>,
+    params: Res<DragSelectParams>,
+    mut drag_state: ResMut<DragSelectState>,
+    mut selection_actions: ResMut<Events<PickSelectionAction>>,
+    mouse_button_inputs: Res<Input<MouseButton>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    diagnostics: Res<PickDiagnostics>,
+    meshes: Res<Assets<Mesh>>,
+    windows: Res<Windows>,
+    // Queries
+    mut mesh_query: Query<With<
+        SelectablePickMesh,
+        (&Handle<Mesh>, &Transform, &PickableMesh, Entity)
+    >>,
+    layers_query: Query<&PickLayers>,
+    mut camera_query: Query<(&Transform, &Camera, Entity)>,
+    mut rect_query: Query<With<DragSelectRect, (&mut Style, &mut Draw)>>,
+    mut lasso_query: Query<With<DragSelectLassoPoint, Entity>>,
+) {
+    let cursor = match pick_state.cursor_position {
+        Some(cursor) => cursor,
+        None => return,
+    };
+    if mouse_button_inputs.just_pressed(MouseButton::Left) {
+        drag_state.start = Some(cursor);
+        drag_state.dragging = false;
+        drag_state.shape = Some(if keyboard_input.pressed(params.lasso_key) {
+            DragSelectShape::Lasso
+        } else {
+            DragSelectShape::Box
+        });
+        drag_state.lasso_points = vec![cursor];
+    }
+    let (start, shape) = match (drag_state.start, drag_state.shape) {
+        (Some(start), Some(shape)) => (start, shape),
+        _ => return,
+    };
+
+    let released = !mouse_button_inputs.pressed(MouseButton::Left);
+    drag_state.dragging = if released {
+        event_state.drag_released()
+    } else {
+        event_state.is_dragging()
+    };
+
+    let rect_min = start.min(cursor);
+    let rect_max = start.max(cursor);
+
+    if shape == DragSelectShape::Lasso && !released {
+        let last_point = *drag_state.lasso_points.last().unwrap_or(&start);
+        if (cursor - last_point).length() >= params.lasso_spacing {
+            drag_state.lasso_points.push(cursor);
+            commands
+                .spawn(NodeComponents {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        position: Rect {
+                            left: Val::Px(cursor.x() - 1.0),
+                            bottom: Val::Px(cursor.y() - 1.0),
+                            ..Default::default()
+                        },
+                        size: Size::new(Val::Px(2.0), Val::Px(2.0)),
+                        ..Default::default()
+                    },
+                    material: drag_state.lasso_material,
+                    ..Default::default()
+                })
+                .with(DragSelectLassoPoint);
+        }
+    }
+
+    for (mut style, mut draw) in &mut rect_query.iter() {
+        if shape == DragSelectShape::Box && drag_state.dragging && !released {
+            let size = rect_max - rect_min;
+            style.position.left = Val::Px(rect_min.x());
+            style.position.bottom = Val::Px(rect_min.y());
+            style.size = Size::new(Val::Px(size.x()), Val::Px(size.y()));
+            draw.is_visible = true;
+        } else if draw.is_visible {
+            draw.is_visible = false;
+        }
+    }
+
+    if !released {
+        return;
+    }
+    for entity in &mut lasso_query.iter() {
+        commands.despawn(entity);
+    }
+    let dragged = drag_state.dragging;
+    let lasso_points = std::mem::replace(&mut drag_state.lasso_points, Vec::new());
+    drag_state.start = None;
+    drag_state.dragging = false;
+    drag_state.shape = None;
+    if !dragged {
+        return;
+    }
+
+    let mode = match (params.mode, shape) {
+        (BoxSelectMode::ByDragDirection, DragSelectShape::Lasso) => BoxSelectMode::Window,
+        (BoxSelectMode::ByDragDirection, _) if cursor.x() >= start.x() => BoxSelectMode::Window,
+        (BoxSelectMode::ByDragDirection, _) => BoxSelectMode::Crossing,
+        (mode, _) => mode,
+    };
+
+    // The region is selected in the view of the camera the drag started in
+    let window_id = match pick_state.cursor_window() {
+        Some(window_id) => window_id,
+        None => return,
+    };
+    let camera = match camera_at_position(window_id, start, &windows, &mut camera_query) {
+        Some(camera) => camera,
+        None => return,
+    };
+
+    // Both shapes are tested as a polygon in NDC, a box is simply a polygon with four corners.
+    let polygon = match shape {
+        DragSelectShape::Box => vec![
+            rect_min,
+            Vec2::new(rect_max.x(), rect_min.y()),
+            rect_max,
+            Vec2::new(rect_min.x(), rect_max.y()),
+        ],
+        DragSelectShape::Lasso => lasso_points,
+    };
+    // A drag that came back to where it started encloses nothing, and shouldn't clear the selection
+    if polygon.len() < 3 || polygon_area(&polygon) < DRAG_THRESHOLD * DRAG_THRESHOLD {
+        return;
+    }
+    let polygon: Vec<Vec2> = polygon
+        .into_iter()
+        .map(|point| camera.screen_to_ndc(point))
+        .collect();
+    let view_projection = camera.view_projection();
+
+    let mut enclosed = Vec::new();
+    for (mesh_handle, transform, pickable, entity) in &mut mesh_query.iter() {
+        // Only meshes on the layers picked with the cursor can be drag selected
+        let layers = match layers_query.get::<PickLayers>(entity) {
+            Ok(layers) => *layers,
+            Err(_) => PickLayers::default(),
+        };
+        if !layers.intersects(&picking_params.layers()) {
+            continue;
+        }
+        // Invalid meshes can't be selected, and their bounding spheres are out of date
+        if diagnostics.has_mesh_error(entity) {
+            continue;
+        }
+        if let Some(mesh) = meshes.get(mesh_handle) {
+            // Most meshes are entirely inside or outside the polygon, which their bounding sphere
+            // shows without projecting every vertex
+            let bounding_sphere = &pickable.bounding_sphere;
+            let in_polygon = match sphere_in_polygon(bounding_sphere, &view_projection, &polygon) {
+                Some(in_polygon) => in_polygon,
+                None => {
+                    let mesh_to_ndc = view_projection * transform.value;
+                    mesh_in_polygon(mesh, &mesh_to_ndc, &polygon, mode)
+                }
+            };
+            if in_polygon {
+                enclosed.push(entity);
+            }
+        }
+    }
+
+    selection_actions.send(match ClickMode::from_keyboard(&keyboard_input) {
+        ClickMode::Replace => PickSelectionAction::Set(enclosed),
+        ClickMode::Add => PickSelectionAction::Add(enclosed),
+        ClickMode::Toggle => PickSelectionAction::Toggle(enclosed),
+    });
+}
+
+/// Checks a mesh's bounding sphere against a polygon in NDC. Returns `Some(false)` if the sphere's
+/// screen space bounds miss the polygon, and `Some(true)` if they are entirely inside it, in which
+/// case every primitive is too, and the mesh is selected in either mode. Returns `None` if the mesh
+/// has to be tested primitive by primitive, including when the sphere is partially behind the
+/// camera.
+fn sphere_in_polygon(
+    bounding_sphere: &BoundSphere,
+    view_projection: &Mat4,
+    polygon: &[Vec2],
+) -> Option<bool> {
+    // The screen space bounds of the corners of the box around the sphere
+    let aabb = bounding_sphere.world_aabb();
+    let mut min = Vec2::splat(f32::MAX);
+    let mut max = Vec2::splat(f32::MIN);
+    for corner in 0..8 {
+        let point = Vec3::new(
+            if corner & 1 == 0 { aabb.min.x() } else { aabb.max.x() },
+            if corner & 2 == 0 { aabb.min.y() } else { aabb.max.y() },
+            if corner & 4 == 0 { aabb.min.z() } else { aabb.max.z() },
+        );
+        let (ndc, w) = project_to_ndc(view_projection, point);
+        if w <= 0.0 || ndc.z() < 0.0 {
+            return None;
+        }
+        min = min.min(Vec2::new(ndc.x(), ndc.y()));
+        max = max.max(Vec2::new(ndc.x(), ndc.y()));
+    }
+
+    let polygon_min = polygon.iter().fold(Vec2::splat(f32::MAX), |min, point| min.min(*point));
+    let polygon_max = polygon.iter().fold(Vec2::splat(f32::MIN), |max, point| max.max(*point));
+    if max.cmplt(polygon_min).any() || min.cmpgt(polygon_max).any() {
+        return Some(false);
+    }
+    // The bounds are inside the polygon if all of their corners are, and no edge of the polygon
+    // crosses into them
+    let corners = [min, Vec2::new(max.x(), min.y()), max, Vec2::new(min.x(), max.y())];
+    let inside = corners.iter().all(|corner| point_in_poly(corner, polygon))
+        && (0..4).all(|i| {
+            let (a, b) = (&corners[i], &corners[(i + 1) % 4]);
+            (0..polygon.len()).all(|j| {
+                !segments_intersect(a, b, &polygon[j], &polygon[(j + 1) % polygon.len()])
+            })
+        });
+    if inside {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// Projects the primitives of a mesh into NDC and checks them against a polygon. In `Window` mode
+/// every primitive must be inside the polygon, in `Crossing` mode a single primitive touching it is
+/// enough. Primitives that are partially behind the camera can never be inside the polygon.
+fn mesh_in_polygon(
+    mesh: &Mesh,
+    mesh_to_ndc: &Mat4,
+    polygon: &[Vec2],
+    mode: BoxSelectMode,
+) -> bool {
+    let (vertex_positions, primitives) = match mesh_vertex_positions(mesh) {
+        Ok(vertex_positions) => match mesh_primitives(mesh, vertex_positions.len()) {
+            Ok(primitives) => (vertex_positions, primitives),
+            Err(_) => return false,
+        },
+        Err(_) => return false,
+    };
+    // Vertices are shared by many primitives, so each one is projected once. Vertices behind the
+    // camera are `None`.
+    let projected: Vec<Option<Vec2>> = vertex_positions
+        .iter()
+        .map(|position| {
+            let (ndc, w) = project_to_ndc(mesh_to_ndc, Vec3::from(*position));
+            if w <= 0.0 || ndc.z() < 0.0 {
+                None
+            } else {
+                Some(Vec2::new(ndc.x(), ndc.y()))
+            }
+        })
+        .collect();
+    match primitives {
+        MeshPrimitives::Triangles(triangles) => {
+            let triangles = triangles.iter().map(|triangle| &triangle[..]);
+            primitives_in_polygon(triangles, &projected, polygon, mode)
+        }
+        MeshPrimitives::Lines(lines) => {
+            let lines = lines.iter().map(|line| &line[..]);
+            primitives_in_polygon(lines, &projected, polygon, mode)
+        }
+        MeshPrimitives::Points(points) => {
+            let points = points.iter().map(std::slice::from_ref);
+            primitives_in_polygon(points, &projected, polygon, mode)
+        }
+    }
+}
+
+/// Checks projected primitives against a polygon for `mesh_in_polygon`. Each primitive is given by
+/// the indices of its vertices in `projected`.
+fn primitives_in_polygon<'a>(
+    primitives: impl Iterator<Item = &'a [u32]>,
+    projected: &[Option<Vec2>],
+    polygon: &[Vec2],
+    mode: BoxSelectMode,
+) -> bool {
+    let mut primitive_found = false;
+    for primitive in primitives {
+        let mut vertices = [Vec2::zero(); 3];
+        let mut behind_camera = false;
+        for (vertex, index) in vertices.iter_mut().zip(primitive.iter()) {
+            match projected[*index as usize] {
+                Some(position) => *vertex = position,
+                None => behind_camera = true,
+            }
+        }
+        let vertices = &vertices[..primitive.len()];
+        match mode {
+            BoxSelectMode::Crossing => {
+                if !behind_camera && primitive_touches_poly(vertices, polygon) {
+                    return true;
+                }
+            }
+            _ => {
+                if behind_camera
+                    || !vertices
+                        .iter()
+                        .all(|vertex| point_in_poly(vertex, polygon))
+                {
+                    return false;
+                }
+                primitive_found = true;
+            }
+        }
+    }
+    mode != BoxSelectMode::Crossing && primitive_found
+}
+
+/// The area enclosed by a polygon, using the shoelace formula. Self-intersecting polygons count the
+/// parts wound in opposite directions against each other, so a lasso that doubles back on itself
+/// has a small area.
+fn polygon_area(polygon: &[Vec2]) -> f32 {
+    let twice_area: f32 = (0..polygon.len())
+        .map(|i| {
+            let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+            a.x() * b.y() - b.x() * a.y()
+        })
+        .sum();
+    twice_area.abs() / 2.0
+}
+
+/// Checks if a projected triangle, line segment or point overlaps a polygon
+fn primitive_touches_poly(vertices: &[Vec2], polygon: &[Vec2]) -> bool {
+    match vertices {
+        [a, b, c] => tri_touches_poly(&[*a, *b, *c], polygon),
+        [a, b] => {
+            point_in_poly(a, polygon)
+                || point_in_poly(b, polygon)
+                || (0..polygon.len()).any(|j| {
+                    segments_intersect(a, b, &polygon[j], &polygon[(j + 1) % polygon.len()])
+                })
+        }
+        [point] => point_in_poly(point, polygon),
+        _ => false,
+    }
+}
+
+/// Checks if a triangle overlaps a polygon: either one contains a vertex of the other, or their
+/// edges cross.
+fn tri_touches_poly(triangle: &[Vec2; 3], polygon: &[Vec2]) -> bool {
+    if triangle.iter().any(|vertex| point_in_poly(vertex, polygon)) {
+        return true;
+    }
+    if polygon
+        .iter()
+        .any(|point| point_in_tri(point, &triangle[0], &triangle[1], &triangle[2]))
+    {
+        return true;
+    }
+    (0..3).any(|i| {
+        let (a, b) = (&triangle[i], &triangle[(i + 1) % 3]);
+        (0..polygon.len()).any(|j| {
+            segments_intersect(a, b, &polygon[j], &polygon[(j + 1) % polygon.len()])
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(min: f32, max: f32) -> Vec<Vec2> {
+        vec![
+            Vec2::new(min, min),
+            Vec2::new(max, min),
+            Vec2::new(max, max),
+            Vec2::new(min, max),
+        ]
+    }
+
+    #[test]
+    fn sphere_in_polygon_broad_phase() {
+        // An orthographic camera at z = 5 looking down -z, so world x and y are NDC x and y
+        let view_projection = Mat4::orthographic_rh(-1.0, 1.0, -1.0, 1.0, 0.0, 10.0)
+            * Mat4::from_translation(Vec3::new(0.0, 0.0, 5.0)).inverse();
+        let sphere = |world_center: Vec3| BoundSphere {
+            mesh_radius: 0.1,
+            world_center,
+            world_radius: 0.1,
+        };
+        let centered = sphere(Vec3::zero());
+        assert_eq!(
+            sphere_in_polygon(&centered, &view_projection, &square(-0.5, 0.5)),
+            Some(true)
+        );
+        assert_eq!(
+            sphere_in_polygon(&centered, &view_projection, &square(0.5, 0.9)),
+            Some(false)
+        );
+        // Partially covered spheres need the narrow phase
+        assert_eq!(
+            sphere_in_polygon(&centered, &view_projection, &square(0.05, 0.5)),
+            None
+        );
+        // A lasso around the sphere, with a notch cut into the middle of it
+        let notched = vec![
+            Vec2::new(-0.5, -0.5),
+            Vec2::new(0.0, -0.5),
+            Vec2::new(0.0, 0.05),
+            Vec2::new(0.01, -0.5),
+            Vec2::new(0.5, -0.5),
+            Vec2::new(0.5, 0.5),
+            Vec2::new(-0.5, 0.5),
+        ];
+        assert_eq!(sphere_in_polygon(&centered, &view_projection, &notched), None);
+        let behind_camera = sphere(Vec3::new(0.0, 0.0, 6.0));
+        assert_eq!(
+            sphere_in_polygon(&behind_camera, &view_projection, &square(-0.5, 0.5)),
+            None
+        );
+    }
+}
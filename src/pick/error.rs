@@ -0,0 +1,99 @@
+use super::*;
+use std::{collections::HashMap, fmt};
+
+/// A problem with a pickable entity or its assets. Entities with errors are skipped by picking or
+/// highlighting instead of panicking, and are listed in `PickDiagnostics`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PickError {
+    /// The mesh has no `VertexAttribute::POSITION` attribute
+    MissingPositions,
+    /// The mesh's positions are in a format that can't be used as 3D points
+    UnsupportedPositionFormat,
+    /// The mesh has an index that refers to a vertex past the end of its positions
+    IndexOutOfBounds { index: u32, vertex_count: usize },
+    /// The entity's material handle doesn't refer to a loaded `StandardMaterial`
+    MissingMaterial,
+}
+
+impl PickError {
+    /// Errors caused by the entity's mesh, rather than its material
+    fn is_mesh_error(&self) -> bool {
+        !matches!(self, PickError::MissingMaterial)
+    }
+}
+
+impl fmt::Display for PickError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PickError::MissingPositions => write!(f, "mesh has no vertex positions"),
+            PickError::UnsupportedPositionFormat => {
+                write!(f, "mesh vertex positions have an unsupported format")
+            }
+            PickError::IndexOutOfBounds {
+                index,
+                vertex_count,
+            } => write!(
+                f,
+                "mesh index {} is out of bounds for {} vertices",
+                index, vertex_count
+            ),
+            PickError::MissingMaterial => write!(f, "material is not loaded"),
+        }
+    }
+}
+
+impl std::error::Error for PickError {}
+
+/// Lists the entities that are currently skipped because of a `PickError`. An entity can have both
+/// a mesh error and a material error, each is kept until its own problem is fixed, for example when
+/// the mesh asset is modified. Each error is logged once when it is first found.
+#[derive(Debug, Default)]
+pub struct PickDiagnostics {
+    errors: HashMap<Entity, Vec<PickError>>,
+}
+
+impl PickDiagnostics {
+    pub fn errors(&self) -> &HashMap<Entity, Vec<PickError>> {
+        &self.errors
+    }
+
+    pub fn get(&self, entity: Entity) -> &[PickError] {
+        self.errors.get(&entity).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Records an error, replacing any previous error of the same kind, mesh or material
+    pub(crate) fn report(&mut self, entity: Entity, error: PickError) {
+        let errors = self.errors.entry(entity).or_insert_with(Vec::new);
+        if errors.contains(&error) {
+            return;
+        }
+        eprintln!("Picking skipped entity {:?}: {}", entity, error);
+        errors.retain(|existing| existing.is_mesh_error() != error.is_mesh_error());
+        errors.push(error);
+    }
+
+    pub(crate) fn has_mesh_error(&self, entity: Entity) -> bool {
+        self.get(entity).iter().any(PickError::is_mesh_error)
+    }
+
+    pub(crate) fn clear_mesh_error(&mut self, entity: Entity) {
+        self.clear(entity, true);
+    }
+
+    pub(crate) fn clear_material_error(&mut self, entity: Entity) {
+        self.clear(entity, false);
+    }
+
+    pub(crate) fn remove(&mut self, entity: Entity) {
+        self.errors.remove(&entity);
+    }
+
+    fn clear(&mut self, entity: Entity, mesh_errors: bool) {
+        if let Some(errors) = self.errors.get_mut(&entity) {
+            errors.retain(|error| error.is_mesh_error() != mesh_errors);
+            if errors.is_empty() {
+                self.errors.remove(&entity);
+            }
+        }
+    }
+}
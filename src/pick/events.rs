@@ -0,0 +1,189 @@
+use super::*;
+use std::collections::HashMap;
+
+/// Maximum time in seconds between two clicks on the same entity for them to be a double click
+const DOUBLE_CLICK_TIME: f64 = 0.3;
+
+/// Sent when the cursor starts hovering over a pickable entity
+#[derive(Debug, Clone)]
+pub struct PickHoverStarted {
+    pub entity: Entity,
+    pub hit: PickDepth,
+}
+
+/// Sent when the cursor stops hovering over a pickable entity. `hit` is the last hit on the entity
+/// before the cursor left it.
+#[derive(Debug, Clone)]
+pub struct PickHoverEnded {
+    pub entity: Entity,
+    pub hit: PickDepth,
+}
+
+/// Sent when the left mouse button is pressed and released over the same entity without dragging
+#[derive(Debug, Clone)]
+pub struct PickClicked {
+    pub entity: Entity,
+    pub hit: PickDepth,
+}
+
+/// Sent on the second of two clicks on the same entity within `DOUBLE_CLICK_TIME`. The second click
+/// also sends a `PickClicked` event.
+#[derive(Debug, Clone)]
+pub struct PickDoubleClicked {
+    pub entity: Entity,
+    pub hit: PickDepth,
+}
+
+/// Sent when the cursor is dragged further than `DRAG_THRESHOLD` after the left mouse button was
+/// pressed over an entity. `hit` is where the entity was hit when the button was pressed.
+#[derive(Debug, Clone)]
+pub struct PickDragStarted {
+    pub entity: Entity,
+    pub hit: PickDepth,
+}
+
+/// How a press of the left mouse button ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PressRelease {
+    Click,
+    Drag,
+}
+
+/// Tracks the hover and click state needed to send pick events. This is the only place that tells
+/// clicks from drags, selection and drag selection both use it so they always agree.
+#[derive(Debug, Default)]
+pub struct PickEventState {
+    hovered: HashMap<Entity, PickDepth>,
+    // Cursor position and nearest hit when the left mouse button was pressed
+    press: Option<(Vec2, Option<PickDepth>)>,
+    // Set once the cursor moves further than `DRAG_THRESHOLD` from the press, and stays set until
+    // the button is released, even if the cursor comes back
+    dragging: bool,
+    // How the press ended, only set on the frame the button was released
+    release: Option<PressRelease>,
+    // Entity and time of the last click, used to detect double clicks
+    last_click: Option<(Entity, f64)>,
+}
+
+impl PickEventState {
+    /// The entity the left mouse button was pressed on, while the button is held
+    pub(crate) fn pressed_entity(&self) -> Option<Entity> {
+        match &self.press {
+            Some((_, Some(hit))) => Some(hit.entity),
+            _ => None,
+        }
+    }
+    /// Whether the left mouse button is held and has been dragged
+    pub(crate) fn is_dragging(&self) -> bool {
+        self.press.is_some() && self.dragging
+    }
+    /// Whether the left mouse button was released this frame without having been dragged
+    pub(crate) fn clicked(&self) -> bool {
+        self.release == Some(PressRelease::Click)
+    }
+    /// Whether the left mouse button was released this frame at the end of a drag
+    pub(crate) fn drag_released(&self) -> bool {
+        self.release == Some(PressRelease::Drag)
+    }
+}
+
+/// Compares the currently hovered hits with the previous frame's to send hover events, and tracks
+/// the left mouse button to send click, double click, and drag events.
+pub(crate) fn pick_events(
+    // Resources
+    pick_state: Res<PickState>,
+    mut event_state: ResMut<PickEventState>,
+    time: Res<Time>,
+    mouse_button_inputs: Res<Input<MouseButton>>,
+    mut hover_started_events: ResMut<Events<PickHoverStarted>>,
+    mut hover_ended_events: ResMut<Events<PickHoverEnded>>,
+    mut clicked_events: ResMut<Events<PickClicked>>,
+    mut double_clicked_events: ResMut<Events<PickDoubleClicked>>,
+    mut drag_started_events: ResMut<Events<PickDragStarted>>,
+) {
+    // Hover events
+    let hovered: HashMap<Entity, PickDepth> = pick_state
+        .hovered()
+        .iter()
+        .map(|hit| (hit.entity, hit.clone()))
+        .collect();
+    for (entity, hit) in event_state.hovered.iter() {
+        if !hovered.contains_key(entity) {
+            hover_ended_events.send(PickHoverEnded {
+                entity: *entity,
+                hit: hit.clone(),
+            });
+        }
+    }
+    for (entity, hit) in hovered.iter() {
+        if !event_state.hovered.contains_key(entity) {
+            hover_started_events.send(PickHoverStarted {
+                entity: *entity,
+                hit: hit.clone(),
+            });
+        }
+    }
+    event_state.hovered = hovered;
+
+    // Click and drag events
+    event_state.release = None;
+    let cursor = match pick_state.cursor_position {
+        Some(cursor) => cursor,
+        None => return,
+    };
+    let nearest = pick_state.ordered_pick_list.first().cloned();
+    if mouse_button_inputs.just_pressed(MouseButton::Left) {
+        event_state.press = Some((cursor, nearest.clone()));
+        event_state.dragging = false;
+    }
+
+    let (press_position, press_hit) = match &event_state.press {
+        Some((press_position, press_hit)) => (*press_position, press_hit.clone()),
+        None => return,
+    };
+    if !event_state.dragging && (cursor - press_position).length() >= DRAG_THRESHOLD {
+        event_state.dragging = true;
+        if let Some(hit) = press_hit.clone() {
+            drag_started_events.send(PickDragStarted {
+                entity: hit.entity,
+                hit,
+            });
+        }
+    }
+
+    if mouse_button_inputs.pressed(MouseButton::Left) {
+        return;
+    }
+    event_state.press = None;
+    if event_state.dragging {
+        event_state.release = Some(PressRelease::Drag);
+        return;
+    }
+    event_state.release = Some(PressRelease::Click);
+    match (press_hit, nearest) {
+        (Some(press_hit), Some(hit)) if press_hit.entity == hit.entity => {
+            let now = time.seconds_since_startup;
+            let double_click = match event_state.last_click {
+                Some((entity, click_time)) => {
+                    entity == hit.entity && now - click_time <= DOUBLE_CLICK_TIME
+                }
+                None => false,
+            };
+            clicked_events.send(PickClicked {
+                entity: hit.entity,
+                hit: hit.clone(),
+            });
+            if double_click {
+                double_clicked_events.send(PickDoubleClicked {
+                    entity: hit.entity,
+                    hit,
+                });
+                // A third click starts a new double click instead of completing another one
+                event_state.last_click = None;
+            } else {
+                event_state.last_click = Some((hit.entity, now));
+            }
+        }
+        _ => {}
+    }
+}
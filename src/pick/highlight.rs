@@ -0,0 +1,611 @@
+use super::*;
+use std::collections::HashMap;
+
+/// How a mesh looks while it is highlighted
+#[derive(Debug, Clone, PartialEq)]
+pub enum HighlightStyle {
+    /// Leave the mesh as it is
+    None,
+    /// Replace the color of the mesh's material
+    ReplaceColor(Color),
+    /// Multiply the color of the mesh's material by a tint
+    Tint(Color),
+    /// Swap the mesh's material for another material
+    Material(Handle<StandardMaterial>),
+    /// Keep the mesh's material, and draw an outline around the mesh. The outline is a copy of the
+    /// mesh, pushed out along its normals by `width` in the mesh's coordinate system, and turned
+    /// inside out so only its far side shows around the edges of the mesh. Only triangle meshes
+    /// can be outlined.
+    Outline { color: Color, width: f32 },
+}
+
+/// Animates the color of a mesh as it changes between highlight styles. Only the material color is
+/// animated, outlines and the other properties of swapped materials change at the end of the fade.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HighlightAnimation {
+    /// Seconds to fade from one style to the next, or zero to change instantly
+    pub fade_duration: f32,
+    /// Seconds per pulse while the mesh is highlighted, or zero to not pulse
+    pub pulse_period: f32,
+    /// How far each pulse fades back towards the initial color, from 0 to 1
+    pub pulse_amount: f32,
+}
+
+impl HighlightAnimation {
+    fn is_animated(&self) -> bool {
+        self.fade_duration > 0.0 || (self.pulse_period > 0.0 && self.pulse_amount > 0.0)
+    }
+}
+
+/// The highlight style for each `PickInteraction` state other than `Idle`, and how to animate
+/// between them
+#[derive(Debug, Clone, PartialEq)]
+pub struct HighlightStyles {
+    pub hovered: HighlightStyle,
+    pub selected: HighlightStyle,
+    pub hovered_selected: HighlightStyle,
+    pub pressed: HighlightStyle,
+    pub animation: HighlightAnimation,
+}
+
+impl Default for HighlightStyles {
+    fn default() -> Self {
+        let hover_color = Color::rgb(0.3, 0.5, 0.8);
+        let selection_color = Color::rgb(0.3, 0.8, 0.5);
+        HighlightStyles {
+            hovered: HighlightStyle::ReplaceColor(hover_color),
+            selected: HighlightStyle::ReplaceColor(selection_color),
+            hovered_selected: HighlightStyle::ReplaceColor(hover_color),
+            pressed: HighlightStyle::ReplaceColor(selection_color),
+            animation: HighlightAnimation::default(),
+        }
+    }
+}
+
+/// The highlight styles used by every `HighlightablePickMesh` that doesn't have its own
+#[derive(Debug, Default)]
+pub struct PickHighlightParams {
+    styles: HighlightStyles,
+}
+
+impl PickHighlightParams {
+    pub fn styles(&self) -> &HighlightStyles {
+        &self.styles
+    }
+    pub fn set_styles(&mut self, styles: HighlightStyles) {
+        self.styles = styles;
+    }
+    pub fn set_hover_style(&mut self, style: HighlightStyle) {
+        self.styles.hovered = style;
+    }
+    pub fn set_selection_style(&mut self, style: HighlightStyle) {
+        self.styles.selected = style;
+    }
+    pub fn set_hovered_selected_style(&mut self, style: HighlightStyle) {
+        self.styles.hovered_selected = style;
+    }
+    pub fn set_pressed_style(&mut self, style: HighlightStyle) {
+        self.styles.pressed = style;
+    }
+    /// Replaces the color of hovered meshes, whether or not they are selected
+    pub fn set_hover_color(&mut self, color: Color) {
+        self.styles.hovered = HighlightStyle::ReplaceColor(color);
+        self.styles.hovered_selected = HighlightStyle::ReplaceColor(color);
+    }
+    /// Replaces the color of selected meshes that aren't hovered, and of pressed meshes
+    pub fn set_selection_color(&mut self, color: Color) {
+        self.styles.selected = HighlightStyle::ReplaceColor(color);
+        self.styles.pressed = HighlightStyle::ReplaceColor(color);
+    }
+    pub fn set_animation(&mut self, animation: HighlightAnimation) {
+        self.styles.animation = animation;
+    }
+}
+
+/// Meshes with `HighlightablePickMesh` will be highlighted when hovered over. If the mesh also has
+/// the `SelectablePickMesh` component, it will highlight when selected. Meshes use the styles in
+/// `PickHighlightParams`, unless they have their own.
+///
+/// Highlighting swaps the entity's material handle for one of its own highlight materials, and
+/// swaps the initial handle back afterwards. Materials shared with other entities are never
+/// modified, so only the hovered or selected entity changes.
+#[derive(Debug)]
+pub struct HighlightablePickMesh {
+    styles: Option<HighlightStyles>,
+    // The materials and outlines created for the styles, once the initial material has loaded
+    applied: Option<AppliedHighlight>,
+}
+
+impl HighlightablePickMesh {
+    pub fn new() -> Self {
+        HighlightablePickMesh {
+            styles: None,
+            applied: None,
+        }
+    }
+    /// Creates a highlightable mesh with its own styles, instead of those in `PickHighlightParams`
+    pub fn with_styles(styles: HighlightStyles) -> Self {
+        HighlightablePickMesh {
+            styles: Some(styles),
+            applied: None,
+        }
+    }
+    pub fn styles(&self) -> Option<&HighlightStyles> {
+        self.styles.as_ref()
+    }
+    /// Sets the mesh's own styles, or `None` to use the styles in `PickHighlightParams`
+    pub fn set_styles(&mut self, styles: Option<HighlightStyles>) {
+        self.styles = styles;
+    }
+    /// The material the entity had before it was highlighted, once its looks have been created
+    pub(crate) fn initial_material(&self) -> Option<Handle<StandardMaterial>> {
+        self.applied.as_ref().map(|applied| applied.initial)
+    }
+}
+
+/// Marks the outline meshes spawned as children of highlighted entities
+#[derive(Debug)]
+pub struct HighlightOutline;
+
+/// What a highlighted mesh looks like in one state
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HighlightLook {
+    material: Handle<StandardMaterial>,
+    // The albedo of the material, which animations fade to and from
+    color: Color,
+    outline: Option<Entity>,
+}
+
+/// Tracks the fade between looks of an animated highlight
+#[derive(Debug)]
+struct HighlightFade {
+    // A material owned by the entity, whose color is changed every frame while animating
+    material: Handle<StandardMaterial>,
+    // The interaction state being faded to
+    state: PickInteraction,
+    from: Color,
+    start: f64,
+    // The color shown last frame
+    color: Color,
+}
+
+#[derive(Debug)]
+struct AppliedHighlight {
+    // The styles the looks were created for
+    styles: HighlightStyles,
+    initial: Handle<StandardMaterial>,
+    initial_color: Color,
+    hovered: HighlightLook,
+    selected: HighlightLook,
+    hovered_selected: HighlightLook,
+    pressed: HighlightLook,
+    fade: Option<HighlightFade>,
+    // The outlines of the looks, which are shown and hidden as the look changes
+    outlines: Vec<Entity>,
+}
+
+impl AppliedHighlight {
+    fn look(&self, interaction: PickInteraction) -> HighlightLook {
+        match interaction {
+            PickInteraction::Hovered => self.hovered,
+            PickInteraction::Selected => self.selected,
+            PickInteraction::HoveredSelected => self.hovered_selected,
+            PickInteraction::Pressed => self.pressed,
+            PickInteraction::Idle => HighlightLook {
+                material: self.initial,
+                color: self.initial_color,
+                outline: None,
+            },
+        }
+    }
+}
+
+/// The materials, meshes and outlines created for the highlights of each entity. They are tracked
+/// outside of `HighlightablePickMesh`, so they can still be freed after the component is removed.
+#[derive(Debug, Default)]
+pub(crate) struct HighlightAssets {
+    created: HashMap<Entity, CreatedHighlightAssets>,
+}
+
+#[derive(Debug, Default)]
+struct CreatedHighlightAssets {
+    // The entity's material before it was highlighted, restored when the highlight is removed
+    initial: Handle<StandardMaterial>,
+    materials: Vec<Handle<StandardMaterial>>,
+    meshes: Vec<Handle<Mesh>>,
+    outlines: Vec<Entity>,
+}
+
+impl CreatedHighlightAssets {
+    /// Removes the assets, and despawns the outlines that haven't already been despawned along
+    /// with their parent
+    fn free(
+        self,
+        commands: &mut Commands,
+        materials: &mut Assets<StandardMaterial>,
+        meshes: &mut Assets<Mesh>,
+        outline_query: &Query<With<HighlightOutline, Entity>>,
+    ) {
+        for handle in self.materials.iter() {
+            materials.remove(handle);
+        }
+        for handle in self.meshes.iter() {
+            meshes.remove(handle);
+        }
+        for outline in self.outlines {
+            if outline_query.get::<HighlightOutline>(outline).is_ok() {
+                commands.despawn(outline);
+            }
+        }
+    }
+}
+
+/// Creates the highlight materials and outlines of highlightable entities that don't have them
+/// yet, either because they were just added or because their material wasn't loaded when they
+/// were, and recreates them when the entity's styles change. Everything created for an entity is
+/// freed when its `HighlightablePickMesh` is removed or the entity is despawned.
+pub(crate) fn update_highlights(
+    // Commands
+    mut commands: Commands,
+    // Resources
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    highlight_params: Res<PickHighlightParams>,
+    mut highlight_assets: ResMut<HighlightAssets>,
+    mut diagnostics: ResMut<PickDiagnostics>,
+    // Queries
+    mut query: Query<(
+        &mut HighlightablePickMesh,
+        &mut Handle<StandardMaterial>,
+        &Handle<Mesh>,
+        Entity,
+    )>,
+    outline_query: Query<With<HighlightOutline, Entity>>,
+    material_query: Query<&mut Handle<StandardMaterial>>,
+) {
+    for entity in query.removed::<HighlightablePickMesh>() {
+        if let Some(created) = highlight_assets.created.remove(entity) {
+            // If the entity is still alive and showing one of its highlight materials, put its
+            // initial material back before the highlight materials are freed
+            if let Ok(mut material_handle) =
+                material_query.get_mut::<Handle<StandardMaterial>>(*entity)
+            {
+                if created.materials.contains(&*material_handle) {
+                    *material_handle = created.initial;
+                }
+            }
+            created.free(&mut commands, &mut materials, &mut meshes, &outline_query);
+        }
+    }
+
+    for (mut highlightable, mut material_handle, mesh_handle, entity) in &mut query.iter() {
+        let styles = highlightable
+            .styles
+            .as_ref()
+            .unwrap_or(&highlight_params.styles)
+            .clone();
+        let initial = match &highlightable.applied {
+            Some(applied) if applied.styles == styles => continue,
+            Some(applied) => applied.initial,
+            None => *material_handle,
+        };
+        let (albedo, albedo_texture, shaded) = match materials.get(&initial) {
+            Some(material) => (material.albedo, material.albedo_texture, material.shaded),
+            None => {
+                diagnostics.report(entity, PickError::MissingMaterial);
+                continue;
+            }
+        };
+        diagnostics.clear_material_error(entity);
+
+        // Replace the looks of the previous styles. The initial material is restored until
+        // `pick_highlighting` applies the new look for the entity's state.
+        if highlightable.applied.take().is_some() {
+            if let Some(created) = highlight_assets.created.remove(&entity) {
+                created.free(&mut commands, &mut materials, &mut meshes, &outline_query);
+            }
+            if *material_handle != initial {
+                *material_handle = initial;
+            }
+        }
+
+        let mut builder = LookBuilder {
+            commands: &mut commands,
+            materials: &mut materials,
+            meshes: &mut meshes,
+            entity,
+            mesh_handle: *mesh_handle,
+            initial,
+            albedo,
+            albedo_texture,
+            shaded,
+            created: CreatedHighlightAssets {
+                initial,
+                ..Default::default()
+            },
+        };
+        let hovered = builder.build(&styles.hovered);
+        let selected = builder.build(&styles.selected);
+        let hovered_selected = builder.build(&styles.hovered_selected);
+        let pressed = builder.build(&styles.pressed);
+        let fade = if styles.animation.is_animated() {
+            Some(HighlightFade {
+                material: builder.derive_material(albedo),
+                state: PickInteraction::Idle,
+                from: albedo,
+                start: 0.0,
+                color: albedo,
+            })
+        } else {
+            None
+        };
+        highlightable.applied = Some(AppliedHighlight {
+            styles,
+            initial,
+            initial_color: albedo,
+            hovered,
+            selected,
+            hovered_selected,
+            pressed,
+            fade,
+            outlines: builder.created.outlines.clone(),
+        });
+        highlight_assets.created.insert(entity, builder.created);
+    }
+}
+
+/// Creates the materials and outline meshes needed for an entity's highlight styles
+struct LookBuilder<'a> {
+    commands: &'a mut Commands,
+    materials: &'a mut Assets<StandardMaterial>,
+    meshes: &'a mut Assets<Mesh>,
+    entity: Entity,
+    mesh_handle: Handle<Mesh>,
+    // The initial material, and the properties of it that derived materials keep
+    initial: Handle<StandardMaterial>,
+    albedo: Color,
+    albedo_texture: Option<Handle<Texture>>,
+    shaded: bool,
+    created: CreatedHighlightAssets,
+}
+
+impl<'a> LookBuilder<'a> {
+    fn build(&mut self, style: &HighlightStyle) -> HighlightLook {
+        let mut look = HighlightLook {
+            material: self.initial,
+            color: self.albedo,
+            outline: None,
+        };
+        match style {
+            HighlightStyle::None => {}
+            HighlightStyle::ReplaceColor(color) => {
+                look.material = self.derive_material(*color);
+                look.color = *color;
+            }
+            HighlightStyle::Tint(tint) => {
+                let albedo = self.albedo;
+                look.color = Color::rgba(
+                    albedo.r * tint.r,
+                    albedo.g * tint.g,
+                    albedo.b * tint.b,
+                    albedo.a * tint.a,
+                );
+                look.material = self.derive_material(look.color);
+            }
+            HighlightStyle::Material(material) => {
+                look.material = *material;
+                if let Some(material) = self.materials.get(material) {
+                    look.color = material.albedo;
+                }
+            }
+            HighlightStyle::Outline { color, width } => look.outline = self.outline(*color, *width),
+        }
+        look
+    }
+
+    /// Adds a copy of the initial material with a different color
+    fn derive_material(&mut self, albedo: Color) -> Handle<StandardMaterial> {
+        let handle = self.materials.add(StandardMaterial {
+            albedo,
+            albedo_texture: self.albedo_texture,
+            shaded: self.shaded,
+        });
+        self.created.materials.push(handle);
+        handle
+    }
+
+    /// Spawns a hidden outline mesh as a child of the entity, if its mesh can be outlined
+    fn outline(&mut self, color: Color, width: f32) -> Option<Entity> {
+        let mesh = outline_mesh(self.meshes.get(&self.mesh_handle)?, width)?;
+        let material = self.materials.add(StandardMaterial {
+            albedo: color,
+            albedo_texture: None,
+            shaded: false,
+        });
+        self.created.materials.push(material);
+        let mesh = self.meshes.add(mesh);
+        self.created.meshes.push(mesh);
+        let outline = self
+            .commands
+            .spawn(PbrComponents {
+                mesh,
+                material,
+                draw: Draw {
+                    is_visible: false,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .with(HighlightOutline)
+            .current_entity()?;
+        self.commands.push_children(self.entity, &[outline]);
+        self.created.outlines.push(outline);
+        Some(outline)
+    }
+}
+
+/// Builds the outline of a triangle mesh: a copy of the mesh with every vertex pushed out along its
+/// normal by `width`, and the winding of every triangle reversed. With back face culling, only the
+/// inside of the far side of the copy is drawn, which shows as an outline around the mesh. Normals
+/// are averaged between vertices at the same position, so hard edges don't split the outline.
+fn outline_mesh(mesh: &Mesh, width: f32) -> Option<Mesh> {
+    let vertex_positions = mesh_vertex_positions(mesh).ok()?;
+    let triangles = match mesh_primitives(mesh, vertex_positions.len()).ok()? {
+        MeshPrimitives::Triangles(triangles) => triangles,
+        _ => return None,
+    };
+    // Meshes without normals are pushed out from their origin instead
+    let vertex_normals = mesh_vertex_normals(mesh, vertex_positions.len())
+        .unwrap_or_else(|| vertex_positions.clone());
+
+    let position_key = |position: &[f32; 3]| {
+        [position[0].to_bits(), position[1].to_bits(), position[2].to_bits()]
+    };
+    let mut smooth_normals: HashMap<[u32; 3], Vec3> = HashMap::new();
+    for (position, normal) in vertex_positions.iter().zip(vertex_normals.iter()) {
+        *smooth_normals
+            .entry(position_key(position))
+            .or_insert_with(Vec3::zero) += Vec3::from(*normal);
+    }
+
+    let mut positions = Vec::with_capacity(vertex_positions.len());
+    let mut normals = Vec::with_capacity(vertex_positions.len());
+    for position in vertex_positions.iter() {
+        let normal = smooth_normals[&position_key(position)];
+        let normal = if normal.length_squared() > 0.0 {
+            normal.normalize()
+        } else {
+            normal
+        };
+        positions.push((Vec3::from(*position) + normal * width).into());
+        // The outline is inside out, so its normals point inwards
+        normals.push((-normal).into());
+    }
+    let indices = triangles
+        .iter()
+        .flat_map(|triangle| vec![triangle[0], triangle[2], triangle[1]])
+        .collect();
+
+    Some(Mesh {
+        primitive_topology: PrimitiveTopology::TriangleList,
+        attributes: vec![
+            VertexAttribute::position(positions),
+            VertexAttribute::normal(normals),
+            VertexAttribute::uv(vec![[0.0, 0.0]; vertex_positions.len()]),
+        ],
+        indices: Some(indices),
+    })
+}
+
+/// Swaps each mesh's material handle for the look of its `PickInteraction` state, and shows its
+/// outline if the look has one. Components are only written on transitions between looks, except
+/// for animated meshes, which show their own material while fading or pulsing, with its color
+/// updated every frame. Entities whose looks haven't been created yet are skipped.
+pub(crate) fn pick_highlighting(
+    // Resources
+    time: Res<Time>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    // Queries
+    mut query: Query<(
+        &mut HighlightablePickMesh,
+        &PickableMesh,
+        &mut Handle<StandardMaterial>,
+    )>,
+    outline_query: Query<With<HighlightOutline, &mut Draw>>,
+) {
+    let now = time.seconds_since_startup;
+    for (mut highlightable, pickable, mut material_handle) in &mut query.iter() {
+        let state = pickable.interaction;
+        let (look, animation, initial_color, animating) = match &highlightable.applied {
+            Some(applied) => {
+                let animating = applied.fade.as_ref().map_or(false, |fade| {
+                    fade.state != state || !fade_finished(fade, &applied.styles.animation, now)
+                });
+                let look = applied.look(state);
+                (look, applied.styles.animation, applied.initial_color, animating)
+            }
+            None => continue,
+        };
+
+        let mut shown_material = look.material;
+        // Only borrow the component mutably while animating, so it isn't marked as changed
+        let fade = if animating {
+            highlightable.applied.as_mut().and_then(|applied| applied.fade.as_mut())
+        } else {
+            None
+        };
+        if let Some(fade) = fade {
+            if fade.state != state {
+                fade.state = state;
+                fade.from = fade.color;
+                fade.start = now;
+            }
+            let color = fade_color(fade, &animation, look.color, initial_color, now);
+            fade.color = color;
+            // Once the fade has finished on a state that doesn't pulse, the look's own material is
+            // shown again, so the animated material only needs to be updated while it's visible
+            if !fade_finished(fade, &animation, now) {
+                shown_material = fade.material;
+                if let Some(material) = materials.get_mut(&fade.material) {
+                    material.albedo = color;
+                }
+            }
+        }
+        if *material_handle != shown_material {
+            *material_handle = shown_material;
+        }
+
+        if let Some(applied) = &highlightable.applied {
+            for outline in applied.outlines.iter() {
+                if let Ok(mut draw) = outline_query.get_mut::<Draw>(*outline) {
+                    let visible = look.outline == Some(*outline);
+                    if draw.is_visible != visible {
+                        draw.is_visible = visible;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Checks if a fade has reached its target look. Highlighted states that pulse never finish.
+fn fade_finished(fade: &HighlightFade, animation: &HighlightAnimation, now: f64) -> bool {
+    let pulsing = animation.pulse_period > 0.0
+        && animation.pulse_amount > 0.0
+        && fade.state != PickInteraction::Idle;
+    !pulsing && now - fade.start >= animation.fade_duration as f64
+}
+
+/// The color of an animated highlight: a fade from the color shown when the state last changed to
+/// the color of the new look, followed by pulses back towards the initial color while highlighted.
+fn fade_color(
+    fade: &HighlightFade,
+    animation: &HighlightAnimation,
+    target: Color,
+    initial_color: Color,
+    now: f64,
+) -> Color {
+    let elapsed = (now - fade.start) as f32;
+    let progress = if animation.fade_duration > 0.0 {
+        (elapsed / animation.fade_duration).min(1.0)
+    } else {
+        1.0
+    };
+    let mut color = lerp_color(fade.from, target, progress);
+    if progress >= 1.0 && !fade_finished(fade, animation, now) {
+        // Pulses start at the look's color, and smoothly fade towards the initial color and back
+        let phase = (elapsed - animation.fade_duration) / animation.pulse_period;
+        let pulse = (1.0 - (phase * std::f32::consts::PI * 2.0).cos()) / 2.0;
+        color = lerp_color(color, initial_color, pulse * animation.pulse_amount);
+    }
+    color
+}
+
+fn lerp_color(from: Color, to: Color, t: f32) -> Color {
+    Color::rgba(
+        from.r + (to.r - from.r) * t,
+        from.g + (to.g - from.g) * t,
+        from.b + (to.b - from.b) * t,
+        from.a + (to.a - from.a) * t,
+    )
+}
@@ -0,0 +1,62 @@
+use super::*;
+
+/// The interaction state of a `PickableMesh`, derived each frame from whether it is hovered,
+/// selected, and pressed with the left mouse button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PickInteraction {
+    /// Not hovered or selected
+    #[default]
+    Idle,
+    /// Under the cursor, and not selected
+    Hovered,
+    /// Selected, and not under the cursor
+    Selected,
+    /// Selected and under the cursor
+    HoveredSelected,
+    /// The left mouse button was pressed on the mesh, and is still held with the cursor over it
+    Pressed,
+}
+
+impl PickInteraction {
+    fn new(hovered: bool, selected: bool, pressed: bool) -> Self {
+        match (hovered, selected, pressed) {
+            (true, _, true) => PickInteraction::Pressed,
+            (true, true, false) => PickInteraction::HoveredSelected,
+            (true, false, false) => PickInteraction::Hovered,
+            (false, true, _) => PickInteraction::Selected,
+            (false, false, _) => PickInteraction::Idle,
+        }
+    }
+}
+
+/// Moves each `PickableMesh` to its new interaction state. The component is only written on a
+/// transition, so systems can use `Changed<PickableMesh>` to react to state changes. While the
+/// cursor is over a row of the select other list, the row's candidate is hovered instead of the
+/// mesh under the cursor, so the row can be matched to its mesh.
+pub(crate) fn update_interactions(
+    // Resources
+    pick_state: Res<PickState>,
+    event_state: Res<PickEventState>,
+    selection_state: Res<PickSelectionState>,
+    // Queries
+    mut query: Query<(&mut PickableMesh, Entity)>,
+    query_selectables: Query<&SelectablePickMesh>,
+) {
+    let pressed_entity = event_state.pressed_entity();
+    let list_candidate = hovered_list_candidate(&selection_state, &pick_state);
+    for (mut pickable, entity) in &mut query.iter() {
+        let selected = match query_selectables.get::<SelectablePickMesh>(entity) {
+            Ok(selectable) => selectable.selected,
+            Err(_) => false,
+        };
+        let hovered = match list_candidate {
+            Some(candidate) => candidate == entity,
+            None => pickable.picked,
+        };
+        let interaction =
+            PickInteraction::new(hovered, selected, pressed_entity == Some(entity));
+        if pickable.interaction != interaction {
+            pickable.interaction = interaction;
+        }
+    }
+}
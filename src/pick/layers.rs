@@ -0,0 +1,52 @@
+use super::*;
+
+/// The pick layers an entity is on, as a bitmask of up to 32 layers. Rays only hit entities that
+/// share at least one layer with the ray cast, so helpers like gizmos can be kept out of normal
+/// selection, while tools that cast their own rays can still pick them. Entities without this
+/// component are on the default layer, layer 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PickLayers {
+    mask: u32,
+}
+
+impl PickLayers {
+    /// No layers, nothing will be hit
+    pub fn none() -> Self {
+        PickLayers { mask: 0 }
+    }
+    /// Every layer
+    pub fn all() -> Self {
+        PickLayers { mask: u32::MAX }
+    }
+    /// Only the given layer. Layers 32 and above don't exist, and are ignored.
+    pub fn layer(layer: u8) -> Self {
+        PickLayers::none().with(layer)
+    }
+    /// Adds a layer
+    pub fn with(mut self, layer: u8) -> Self {
+        self.mask |= Self::layer_bit(layer);
+        self
+    }
+    /// Removes a layer
+    pub fn without(mut self, layer: u8) -> Self {
+        self.mask &= !Self::layer_bit(layer);
+        self
+    }
+    pub fn contains(&self, layer: u8) -> bool {
+        self.mask & Self::layer_bit(layer) != 0
+    }
+    /// Checks if the two sets of layers share any layer
+    pub fn intersects(&self, other: &PickLayers) -> bool {
+        self.mask & other.mask != 0
+    }
+
+    fn layer_bit(layer: u8) -> u32 {
+        1u32.checked_shl(layer as u32).unwrap_or(0)
+    }
+}
+
+impl Default for PickLayers {
+    fn default() -> Self {
+        PickLayers::layer(0)
+    }
+}
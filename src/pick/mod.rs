@@ -1,21 +1,81 @@
 use bevy::{
+    asset::AssetEvent,
+    input::mouse::MouseButton,
     prelude::*,
     render::camera::Camera,
+    ui::camera::UI_CAMERA,
     render::mesh::{VertexAttribute, VertexAttributeValues},
     render::pipeline::PrimitiveTopology,
     render::color::Color,
-    window::CursorMoved,
+    window::{CursorMoved, WindowId},
 };
+use std::borrow::Cow;
+
+mod alpha;
+mod bvh;
+mod camera;
+mod drag_select;
+mod error;
+mod events;
+mod highlight;
+mod interaction;
+mod layers;
+mod raycast;
+mod scene_index;
+mod select;
+mod select_other_list;
+pub use alpha::*;
+pub use bvh::*;
+pub use camera::*;
+pub use drag_select::*;
+pub use error::*;
+pub use events::*;
+pub use highlight::*;
+pub use interaction::*;
+pub use layers::*;
+pub use raycast::*;
+pub use scene_index::*;
+pub use select::*;
+pub use select_other_list::*;
+
+/// Distance in pixels the cursor must move while a mouse button is held before the interaction is
+/// treated as a drag instead of a click.
+const DRAG_THRESHOLD: f32 = 4.0;
 
 pub struct PickingPlugin;
 impl Plugin for PickingPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.init_resource::<PickState>()
+            .init_resource::<PickingParams>()
+            .init_resource::<PickDiagnostics>()
+            .init_resource::<PickSelectionState>()
+            .init_resource::<PickBvhCache>()
+            .init_resource::<PickSceneIndex>()
+            .init_resource::<PickEventState>()
             .init_resource::<PickHighlightParams>()
-            .add_startup_system(highlightable_init.system())
-            .add_system(highlightable_added.system())
+            .init_resource::<HighlightAssets>()
+            .init_resource::<DragSelectParams>()
+            .init_resource::<DragSelectState>()
+            .init_resource::<SelectOtherListParams>()
+            .init_resource::<SelectOtherListState>()
+            .add_event::<PickSelectionAction>()
+            .add_event::<PickHoverStarted>()
+            .add_event::<PickHoverEnded>()
+            .add_event::<PickClicked>()
+            .add_event::<PickDoubleClicked>()
+            .add_event::<PickDragStarted>()
+            .add_startup_system(drag_select_init.system())
+            .add_startup_system(select_other_list_init.system())
+            .add_system(spawn_ui_camera.system())
+            .add_system(update_highlights.system())
+            .add_system(update_bound_spheres.system())
+            .add_system(update_mesh_bvhs.system())
             .add_system(pick_mesh.system())
-            //.add_system(select_mesh.system())
+            .add_system(pick_events.system())
+            .add_system(select_mesh.system())
+            .add_system(update_select_other_list.system())
+            .add_system(drag_select.system())
+            .add_system(update_interactions.system())
             .add_system(pick_highlighting.system())
             ;
     }
@@ -23,474 +83,744 @@ impl Plugin for PickingPlugin {
 
 pub struct PickState {
     cursor_event_reader: EventReader<CursorMoved>,
+    mesh_event_reader: EventReader<AssetEvent<Mesh>>,
+    cursor_position: Option<Vec2>,
+    cursor_window: Option<WindowId>,
     ordered_pick_list: Vec<PickDepth>,
+    // Number of hits at the start of the pick list that are hovered
+    hovered_count: usize,
 }
 
 impl PickState {
     pub fn list(&self) -> &Vec<PickDepth> {
         &self.ordered_pick_list
     }
+    /// The hits at the start of the pick list that are hovered, depending on the `PickMode`
+    pub fn hovered(&self) -> &[PickDepth] {
+        &self.ordered_pick_list[..self.hovered_count]
+    }
+    /// The last known cursor position in window pixels, with the origin at the bottom left, or
+    /// `None` if the cursor has not yet moved
+    pub fn cursor_position(&self) -> Option<Vec2> {
+        self.cursor_position
+    }
+    /// The window the cursor last moved in
+    pub fn cursor_window(&self) -> Option<WindowId> {
+        self.cursor_window
+    }
 }
 
 impl Default for PickState {
     fn default() -> Self {
         PickState {
             cursor_event_reader: EventReader::default(),
+            mesh_event_reader: EventReader::default(),
+            cursor_position: None,
+            cursor_window: None,
             ordered_pick_list: Vec::new(),
+            hovered_count: 0,
         }
     }
 }
 
-/// Holds the entity associated with a mesh as well as it's computed depth from a pick ray cast
-#[derive(Debug, PartialOrd, PartialEq)]
-pub struct PickDepth {
-    entity: Entity,
-    ndc_depth: f32,
-}
-impl PickDepth {
-    fn new(entity: Entity, ndc_depth: f32) -> Self {
-        PickDepth{
-            entity,
-            ndc_depth,
-        }
-    }
+/// Which hits in the pick list are hovered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickMode {
+    /// Only the nearest hit is hovered, along with any `PickPassThrough` entities in front of it
+    FrontMost,
+    /// Every hit is hovered, including meshes hidden behind others
+    AllHits,
 }
 
-/// Holds a list of selected meshes by handle
-#[derive(Debug)]
-pub struct PickSelectionState {
-    selected_next: Vec<Handle<Mesh>>,
-    selected_previous: Vec<Handle<Mesh>>,
-}
+/// Marks an entity as transparent to picking, ignoring it for occlusion. It is still hovered when
+/// under the cursor, but it doesn't stop the meshes behind it from being hovered with
+/// `PickMode::FrontMost`. To skip hits on the transparent parts of a mesh, use `PickAlphaTest`.
+#[derive(Debug, Default)]
+pub struct PickPassThrough;
 
 #[derive(Debug)]
-
-pub struct PickHighlightParams {
-    hover_color: Color,
-    selection_color: Color,
+pub struct PickingParams {
+    // Distance in pixels from the cursor within which lines and points are picked
+    pixel_tolerance: f32,
+    // Layers that can be picked with the cursor
+    layers: PickLayers,
+    // Which hits are hovered
+    mode: PickMode,
 }
 
-impl PickHighlightParams {
-    fn set_hover_color(&mut self, color: Color) {
-        self.hover_color = color;
+impl PickingParams {
+    pub fn set_pixel_tolerance(&mut self, pixels: f32) {
+        self.pixel_tolerance = pixels;
+    }
+    pub fn pixel_tolerance(&self) -> f32 {
+        self.pixel_tolerance
     }
-    fn set_selection_color(&mut self, color: Color) {
-        self.selection_color = color;
+    pub fn set_layers(&mut self, layers: PickLayers) {
+        self.layers = layers;
+    }
+    pub fn layers(&self) -> PickLayers {
+        self.layers
+    }
+    pub fn set_mode(&mut self, mode: PickMode) {
+        self.mode = mode;
+    }
+    pub fn mode(&self) -> PickMode {
+        self.mode
     }
 }
 
-impl Default for PickHighlightParams {
+impl Default for PickingParams {
     fn default() -> Self {
-        PickHighlightParams {
-            hover_color: Color::rgb(0.3, 0.5, 0.8),
-            selection_color: Color::rgb(0.3, 0.8, 0.5),
+        PickingParams {
+            pixel_tolerance: 4.0,
+            layers: PickLayers::default(),
+            mode: PickMode::FrontMost,
         }
     }
 }
 
+/// Holds the entity associated with a mesh, it's computed depth from a pick ray cast, and the
+/// details of where the mesh was hit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PickDepth {
+    entity: Entity,
+    distance: f32,
+    position: Vec3,
+    normal: Vec3,
+    primitive_index: usize,
+    barycentric: Vec3,
+}
+impl PickDepth {
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+    /// Distance from the ray origin to the hit, in world units
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+    /// World space position of the hit
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+    /// World space surface normal at the hit. This is interpolated from the vertex normals if the
+    /// mesh has them, otherwise it is the normal of the triangle that was hit. Lines and points
+    /// have no surface, so their normal points back along the ray.
+    pub fn normal(&self) -> Vec3 {
+        self.normal
+    }
+    /// Index of the triangle, line segment or point that was hit, counting primitives in the order
+    /// they appear in the mesh
+    pub fn primitive_index(&self) -> usize {
+        self.primitive_index
+    }
+    /// Barycentric coordinates of the hit within the primitive, weighting each of its vertices.
+    /// Line segments only use the first two coordinates, and points only the first.
+    pub fn barycentric(&self) -> Vec3 {
+        self.barycentric
+    }
+}
+
 /// Marks an entity as pickable
 #[derive(Debug)]
 pub struct PickableMesh {
     bounding_sphere: BoundSphere,
     picked: bool,
+    interaction: PickInteraction,
 }
 
 impl PickableMesh {
+    /// Creates a pickable mesh component. If the mesh is invalid, the error is reported in
+    /// `PickDiagnostics` once the component is added to an entity.
     pub fn new(parent_mesh: &Mesh) -> Self {
         PickableMesh {
-            bounding_sphere: BoundSphere::from(parent_mesh),
+            bounding_sphere: BoundSphere::new(parent_mesh).unwrap_or_default(),
             picked: false,
+            interaction: PickInteraction::Idle,
         }
     }
-    fn update_ndc_bounding_circle() {
-
+    /// Whether the mesh is hovered by the cursor, depending on the `PickMode`
+    pub fn picked(&self) -> bool {
+        self.picked
+    }
+    /// The current interaction state of the mesh
+    pub fn interaction(&self) -> PickInteraction {
+        self.interaction
     }
 }
 
 /// Meshes with `SelectableMesh` will have selection state managed
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct SelectablePickMesh {
     selected: bool
 }
 
-/// Meshes with `HighlightablePickMesh` will be highlighted when hovered over. If the mesh also has
-/// the `SelectablePickMesh` component, it will highlight when selected.
-#[derive(Debug)]
-pub struct HighlightablePickMesh {
-    // Stores the initial color of the mesh material prior to selecting/hovering
-    initial_color: Option<Color>,
-}
-
-impl HighlightablePickMesh {
+impl SelectablePickMesh {
     pub fn new() -> Self {
-        HighlightablePickMesh {
-            initial_color: None,
+        SelectablePickMesh {
+            selected: false,
         }
     }
+    pub fn selected(&self) -> bool {
+        self.selected
+    }
 }
 
-// How to handle bounding spheres?
-// Need to update any time the mesh or its transform changes. Initial query of points remains valid
-// as long as the mesh and scale does not change. Ensure the sphere is centered on the mesh center,
-// so that changes to rotation do not affect the bounding sphere definition, and scale only affects
-// the radius. The mesh query should compute the distance of each point from the origin, and store
-// the maximum value as the radius.
-// In summary:
-// 1. query mesh to determine radius via max
-// 2. on setup, determine the NDC coordinates of the sphere using the entity's mesh translation,
-//    scale(radius), make sure to use the NDC z-coord to divide by w on the radius for perspective
-//    WARNING!!!!: rectilinear perpective warp means bounding sphere will be elliptical(?) need to add
-//    some buffer to ndc radius to account for this as a function of FOV and distance from the ndc 
-//    origin. This is a conic section. http://shaunlebron.github.io/visualizing-projections/
-//    perspective_scaling = sec(arctan(x*tan(b/2))) where b = fov in radians
-//    or sqrt(c*x^2+1) where c has some nonlinear relationship to fov. c = tan(b/2) is close but not
-//    identical: https://www.desmos.com/calculator/v0sf4wota5
-// 3. If the mesh changes, recompute the mesh_radius and ndc_def
-// 4. If the camera or mesh transforms change, update the ndc_def
-
-/// Defines a bounding sphere with a center point coordinate and a radius, used for picking
-#[derive(Debug)]
+/// Defines a bounding sphere centered on the mesh's origin, used to quickly reject meshes that a
+/// pick ray can't hit before testing every triangle. Because the sphere is centered on the origin,
+/// rotating the mesh doesn't change the sphere, and scaling the mesh only changes the radius.
+#[derive(Debug, Default)]
 struct BoundSphere {
+    // Distance from the mesh origin to the furthest vertex, in the mesh's coordinate system
     mesh_radius: f32,
-    ndc_def: Option<NdcBoundingCircle>,
+    // The sphere in world space, updated when the mesh or its transform changes
+    world_center: Vec3,
+    world_radius: f32,
 }
 
-impl From<&Mesh> for BoundSphere {
-    fn from(mesh: &Mesh) -> Self {
+impl BoundSphere {
+    /// Computes the bounding sphere of a mesh, returning an error if the mesh is invalid
+    fn new(mesh: &Mesh) -> Result<Self, PickError> {
+        let vertex_positions = mesh_vertex_positions(mesh)?;
+        // Only vertices used by the mesh's primitives are bounded
+        let indices = mesh_indices(mesh, vertex_positions.len());
+        validate_indices(&indices, vertex_positions.len())?;
         let mut mesh_radius = 0f32;
-        if mesh.primitive_topology != PrimitiveTopology::TriangleList {
-            panic!("Non-TriangleList mesh supplied for bounding sphere generation")
+        for index in indices.iter() {
+            mesh_radius = mesh_radius.max(Vec3::from(vertex_positions[*index as usize]).length());
         }
-        let mut vertex_positions = Vec::new();
-        for attribute in mesh.attributes.iter() {
-            if attribute.name == VertexAttribute::POSITION {
-                vertex_positions = match &attribute.values {
-                    VertexAttributeValues::Float3(positions) => positions.clone(),
-                    _ => panic!("Unexpected vertex types in VertexAttribute::POSITION"),
-                };
-            }
-        }
-        if let Some(indices) = &mesh.indices {
-            for index in indices.iter() {
-                mesh_radius = mesh_radius.max(Vec3::from(vertex_positions[*index as usize]).length());
-            }
-        }
-        BoundSphere {
+        Ok(BoundSphere {
             mesh_radius,
-            ndc_def: None,
-        }
+            world_center: Vec3::zero(),
+            world_radius: mesh_radius,
+        })
     }
-}
 
-/// Created from a BoundSphere, this represents a circle that bounds the entity's mesh when the
-/// bounding sphere is projected onto the screen. Note this is not as simple as transforming the
-/// sphere's origin into ndc and copying the radius. Due to rectillinear projection, the sphere
-/// will be projected onto the screen as an ellipse if it is not perfectly centered at 0,0 in ndc.
-#[derive(Debug)]
-struct NdcBoundingCircle {
-    center: Vec2,
-    radius: f32,
-}
+    /// Moves the sphere into world space using the mesh's transform. The radius is scaled by the
+    /// largest axis scale of the transform, so the sphere still bounds non-uniformly scaled meshes.
+    fn update_world_sphere(&mut self, mesh_to_world: &Mat4) {
+        let max_scale = mesh_to_world
+            .x_axis()
+            .truncate()
+            .length()
+            .max(mesh_to_world.y_axis().truncate().length())
+            .max(mesh_to_world.z_axis().truncate().length());
+        self.world_center = mesh_to_world.w_axis().truncate();
+        self.world_radius = self.mesh_radius * max_scale;
+    }
 
-fn highlightable_init(
-    // Resources
-    materials: ResMut<Assets<StandardMaterial>>,
-    // Queries
-    mut query_picked: Query<(
-        &mut HighlightablePickMesh, 
-        &Handle<StandardMaterial>
-    )>,
-) {
-    for (mut highlightable, material_handle) in &mut query_picked.iter() {
-        if let None = highlightable.initial_color {
-            highlightable.initial_color = Some(materials
-                .get(material_handle)
-                .unwrap()
-                .albedo)
+    /// The world space axis aligned box that contains the sphere
+    fn world_aabb(&self) -> Aabb {
+        let radius = Vec3::splat(self.world_radius);
+        Aabb {
+            min: self.world_center - radius,
+            max: self.world_center + radius,
         }
     }
-}
 
-/// Gets the initial color of newly added highlightable entities
-fn highlightable_added(
-    // Resources
-    materials: ResMut<Assets<StandardMaterial>>,
-    // Queries
-    mut query_picked: Query<With<
-            Added<'static, HighlightablePickMesh>,
-            (&mut HighlightablePickMesh, &Handle<StandardMaterial>)
-        >>,
-) {
-    for (mut highlightable, material_handle) in &mut query_picked.iter() {
-        if let None = highlightable.initial_color {
-            highlightable.initial_color = Some(materials
-                .get(material_handle)
-                .unwrap()
-                .albedo)
+    /// Checks if a world space ray passes through the sphere, ignoring the part of the sphere that
+    /// is entirely behind the ray origin. The sphere is grown by the pick tolerance, so lines and
+    /// points near the edge of the sphere aren't rejected.
+    fn intersects_ray(&self, ray: &Ray3d, tolerance: &PickTolerance) -> bool {
+        let to_center = self.world_center - ray.origin();
+        // Distance along the ray to the point closest to the sphere center
+        let closest_approach = to_center.dot(ray.direction());
+        let radius = self.world_radius
+            + tolerance.at_distance(closest_approach.max(0.0) + self.world_radius);
+        let radius_squared = radius * radius;
+        if to_center.length_squared() - closest_approach * closest_approach > radius_squared {
+            return false;
         }
+        closest_approach >= 0.0 || to_center.length_squared() <= radius_squared
     }
 }
 
-/// Given the current selected and hovered meshes and provided materials, update the meshes with the
-/// appropriate materials.
-fn pick_highlighting(
+/// Keeps the bounding spheres of pickable meshes, and the scene index built from them, up to date.
+/// The mesh radius is recomputed when the mesh asset is modified or the entity's mesh handle
+/// changes, and the world space sphere is recomputed when either the radius or the entity's
+/// transform changes. Entities are removed from the scene index when their `PickableMesh` is, or
+/// when their mesh is invalid.
+fn update_bound_spheres(
     // Resources
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    highlight_params: Res<PickHighlightParams>,
+    mut pick_state: ResMut<PickState>,
+    mut scene_index: ResMut<PickSceneIndex>,
+    mut diagnostics: ResMut<PickDiagnostics>,
+    mesh_events: Res<Events<AssetEvent<Mesh>>>,
+    meshes: Res<Assets<Mesh>>,
     // Queries
-    mut query_picked: Query<(
-        &HighlightablePickMesh,
-        Changed<PickableMesh>,
-        &Handle<StandardMaterial>,
-        Entity,
-    )>,
-    mut query_selected: Query<(
-        &HighlightablePickMesh,
-        Changed<SelectablePickMesh>,
-        &Handle<StandardMaterial>,
-    )>,
-    query_selectables: Query<&SelectablePickMesh>,
+    mut query: Query<(&Handle<Mesh>, &Transform, &mut PickableMesh, Entity)>,
+    mut added_query: Query<With<
+        Added<'static, PickableMesh>,
+        (&Handle<Mesh>, &Transform, &mut PickableMesh, Entity)
+    >>,
+    mut changed_mesh_query: Query<(Changed<Handle<Mesh>>, &Transform, &mut PickableMesh, Entity)>,
+    mut changed_transform_query: Query<(Changed<Transform>, &mut PickableMesh, Entity)>,
 ) {
-    // Query Selectable entities that have changed
-    for (highlightable, selectable, material_handle) in &mut query_selected.iter() {
-        let material_albedo = &mut materials
-            .get_mut(material_handle)
-            .unwrap()
-            .albedo;
-        if selectable.selected {
-            *material_albedo = highlight_params.selection_color;
-        } else {
-            match highlightable.initial_color {
-                Some(color) => *material_albedo = color,
-                None => panic!("Initial color not assigned to `HighlightablePickMesh`")
+    for entity in query.removed::<PickableMesh>() {
+        scene_index.remove(*entity);
+        diagnostics.remove(*entity);
+    }
+
+    let mut modified_meshes = Vec::new();
+    for event in pick_state.mesh_event_reader.iter(&mesh_events) {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                modified_meshes.push(*handle)
             }
+            AssetEvent::Removed { .. } => {}
         }
     }
-
-    // Query Highlightable entities that have changed
-    for (highlightable, pickable, material_handle, entity) in &mut query_picked.iter() {
-        let material_albedo = &mut materials
-            .get_mut(material_handle)
-            .unwrap()
-            .albedo;
-        if pickable.picked {
-            *material_albedo = highlight_params.hover_color;
-        } else {
-            if let Ok(mut query) = query_selectables.entity(entity) {
-                if let Some(selectable) = query.get() {
-                    if selectable.selected {
-                        *material_albedo = highlight_params.selection_color;
-                    } else {
-                        *material_albedo = highlight_params.hover_color;
-                    }
-                }
-            } else {
-                match highlightable.initial_color {
-                    Some(color) => *material_albedo = color,
-                    None => panic!("Initial color not assigned to `HighlightablePickMesh`")
+    if !modified_meshes.is_empty() {
+        for (mesh_handle, transform, mut pickable, entity) in &mut query.iter() {
+            if modified_meshes.contains(mesh_handle) {
+                if let Some(mesh) = meshes.get(mesh_handle) {
+                    update_mesh_bounds(
+                        entity,
+                        mesh,
+                        &transform.value,
+                        &mut pickable,
+                        &mut scene_index,
+                        &mut diagnostics,
+                    );
                 }
             }
         }
     }
-}
-
 
-/*
-/// Given the currently hovered mesh, checks for a user click and if detected, sets the selected
-/// mesh in the MousePicking state resource.
-fn select_mesh(
-    // Resources
-    mut pick_state: ResMut<PickState>,
-    mouse_button_inputs: Res<Input<MouseButton>>,
-    // Queries
-    mut query: Query<(&Handle<Mesh>, &PickableMesh)>,
-) {
-    let mut selection_made = false;
-    let mut pick_stack = pick_state.ordered_pick_list;
-    query.get(entity)
-    for (mesh_handle, _selectable) in &mut query.iter() {
-
-        if let Some(hovered) = pick_state.hovered {
-            // If the current mesh is the one being hovered over, and the left mouse button is
-            // down, set the current mesh to selected.
-            if *mesh_handle == hovered && mouse_button_inputs.pressed(MouseButton::Left) {
-                pick_state.selected_previous = pick_state.selected;
-                // Set the current mesh as the selected mesh.
-                pick_state.selected = Some(*mesh_handle);
-                selection_made = true;
+    for (mesh_handle, transform, mut pickable, entity) in &mut added_query.iter() {
+        match meshes.get(mesh_handle) {
+            Some(mesh) => {
+                update_mesh_bounds(
+                    entity,
+                    mesh,
+                    &transform.value,
+                    &mut pickable,
+                    &mut scene_index,
+                    &mut diagnostics,
+                );
+            }
+            None => {
+                pickable.bounding_sphere.update_world_sphere(&transform.value);
+                scene_index.update(entity, pickable.bounding_sphere.world_aabb());
             }
         }
     }
-    // If nothing is being hovered and the user clicks, deselect the current mesh.
-    if pick_state.hovered == None
-        && mouse_button_inputs.pressed(MouseButton::Left)
-        && pick_state.selected != None
-    {
-        pick_state.selected_previous = pick_state.selected;
-        pick_state.selected = None;
-        selection_made = true;
+
+    for (mesh_handle, transform, mut pickable, entity) in &mut changed_mesh_query.iter() {
+        if let Some(mesh) = meshes.get(&mesh_handle) {
+            update_mesh_bounds(
+                entity,
+                mesh,
+                &transform.value,
+                &mut pickable,
+                &mut scene_index,
+                &mut diagnostics,
+            );
+        }
     }
-    if !selection_made {
-        pick_state.selected_previous = pick_state.selected;
+
+    for (transform, mut pickable, entity) in &mut changed_transform_query.iter() {
+        // Entities with invalid meshes stay out of the scene index until their mesh is fixed
+        if diagnostics.has_mesh_error(entity) {
+            continue;
+        }
+        pickable.bounding_sphere.update_world_sphere(&transform.value);
+        scene_index.update(entity, pickable.bounding_sphere.world_aabb());
+    }
+}
+
+/// Recomputes an entity's bounding sphere from its mesh and updates the scene index. If the mesh is
+/// invalid, the entity is removed from the scene index so it can't be picked, and the error is
+/// reported.
+fn update_mesh_bounds(
+    entity: Entity,
+    mesh: &Mesh,
+    mesh_to_world: &Mat4,
+    pickable: &mut PickableMesh,
+    scene_index: &mut PickSceneIndex,
+    diagnostics: &mut PickDiagnostics,
+) {
+    match BoundSphere::new(mesh) {
+        Ok(bounding_sphere) => {
+            pickable.bounding_sphere = bounding_sphere;
+            pickable.bounding_sphere.update_world_sphere(mesh_to_world);
+            scene_index.update(entity, pickable.bounding_sphere.world_aabb());
+            diagnostics.clear_mesh_error(entity);
+        }
+        Err(error) => {
+            scene_index.remove(entity);
+            diagnostics.report(entity, error);
+        }
     }
 }
-*/
 
 fn pick_mesh(
     // Resources
     mut pick_state: ResMut<PickState>,
+    picking_params: Res<PickingParams>,
     cursor: Res<Events<CursorMoved>>,
     meshes: Res<Assets<Mesh>>,
+    materials: Res<Assets<StandardMaterial>>,
+    textures: Res<Assets<Texture>>,
+    bvh_cache: Res<PickBvhCache>,
+    scene_index: Res<PickSceneIndex>,
     windows: Res<Windows>,
     // Queries
-    mut mesh_query: Query<(&Handle<Mesh>, &Transform, &mut PickableMesh, Entity)>,
-    mut camera_query: Query<(&Transform, &Camera)>,
+    mesh_query: Query<(&Handle<Mesh>, &Transform, &PickableMesh)>,
+    mut pickable_query: Query<(&mut PickableMesh, Entity)>,
+    mut camera_query: Query<(&Transform, &Camera, Entity)>,
 ) {
-    // Get the cursor position
-    let cursor_pos_screen: Vec2 = match pick_state.cursor_event_reader.latest(&cursor) {
-        Some(cursor_moved) => cursor_moved.position,
-        None => return,
+    // Keep the last known cursor position, and the window it is in. Picking is repeated every
+    // frame, so the pick list stays up to date when the camera or meshes move under a still cursor.
+    let cursor_moved = pick_state
+        .cursor_event_reader
+        .latest(&cursor)
+        .map(|cursor_moved| (cursor_moved.position, cursor_moved.id));
+    if let Some((position, window_id)) = cursor_moved {
+        pick_state.cursor_position = Some(position);
+        pick_state.cursor_window = Some(window_id);
+    }
+    let (cursor_pos_screen, cursor_window) =
+        match (pick_state.cursor_position, pick_state.cursor_window) {
+            (Some(position), Some(window_id)) => (position, window_id),
+            _ => return,
+        };
+
+    // Pick with the camera whose viewport the cursor is over. If there isn't one, nothing is
+    // under the cursor.
+    let camera = camera_at_position(cursor_window, cursor_pos_screen, &windows, &mut camera_query);
+    pick_state.ordered_pick_list = match camera {
+        Some(camera) => {
+            // Normalized device coordinates (NDC) describes cursor position from (-1, -1) to
+            // (1, 1) across the camera's viewport
+            let cursor_pos_ndc: Vec2 = camera.screen_to_ndc(cursor_pos_screen);
+
+            // Build a ray in world space from the camera through the cursor
+            let ray = Ray3d::from_screenspace(
+                cursor_pos_ndc,
+                &camera.transform,
+                &camera.projection_matrix,
+            );
+            let options = RayCastOptions {
+                tolerance: PickTolerance::from_screenspace(
+                    picking_params.pixel_tolerance,
+                    &camera.projection_matrix,
+                    camera.viewport_size().y(),
+                ),
+                layers: picking_params.layers,
+                alpha_test: true,
+            };
+
+            let alpha_test_assets = AlphaTestAssets {
+                materials: &materials,
+                textures: &textures,
+            };
+
+            // Cast the ray into the scene, the pick list is sorted by distance, nearest first
+            cast_ray(
+                &ray,
+                &options,
+                &meshes,
+                &alpha_test_assets,
+                &bvh_cache,
+                &scene_index,
+                &mesh_query,
+            )
+        }
+        None => Vec::new(),
+    };
+    pick_state.hovered_count = match picking_params.mode {
+        PickMode::FrontMost => {
+            // Hits are hovered up to and including the first one that isn't transparent to picking
+            let passed_through = pick_state
+                .ordered_pick_list
+                .iter()
+                .take_while(|pick| mesh_query.get::<PickPassThrough>(pick.entity).is_ok())
+                .count();
+            (passed_through + 1).min(pick_state.ordered_pick_list.len())
+        }
+        PickMode::AllHits => pick_state.ordered_pick_list.len(),
     };
 
-    // Get current screen size
-    let window = windows.get_primary().unwrap();
-    let screen_size = Vec2::from([window.width as f32, window.height as f32]);
+    // Only write to components whose state changed, so `Changed<PickableMesh>` queries don't run
+    // for every mesh every frame.
+    for (mut pickable, entity) in &mut pickable_query.iter() {
+        let picked = pick_state
+            .hovered()
+            .iter()
+            .any(|pick| pick.entity == entity);
+        if pickable.picked != picked {
+            pickable.picked = picked;
+        }
+    }
+}
 
-    // Normalized device coordinates (NDC) describes cursor position from (-1, -1) to (1, 1)
-    let cursor_pos_ndc: Vec2 = (cursor_pos_screen / screen_size) * 2.0 - Vec2::from([1.0, 1.0]);
+/// Get the vertex positions of a mesh, in the mesh's coordinate system. Two component positions
+/// are placed on the XY plane, and four component positions are treated as homogeneous
+/// coordinates.
+fn mesh_vertex_positions(mesh: &Mesh) -> Result<Vec<[f32; 3]>, PickError> {
+    let attribute = mesh.attributes.iter()
+        .filter(|attribute| attribute.name == VertexAttribute::POSITION)
+        .last()
+        .ok_or(PickError::MissingPositions)?;
+    match &attribute.values {
+        VertexAttributeValues::Float3(positions) => Ok(positions.clone()),
+        VertexAttributeValues::Float2(positions) => {
+            Ok(positions.iter().map(|[x, y]| [*x, *y, 0.0]).collect())
+        }
+        VertexAttributeValues::Float4(positions) => Ok(positions
+            .iter()
+            .map(|[x, y, z, w]| {
+                let w = if *w == 0.0 { 1.0 } else { *w };
+                [x / w, y / w, z / w]
+            })
+            .collect()),
+        _ => Err(PickError::UnsupportedPositionFormat),
+    }
+}
 
-    // Get the view transform and projection matrix from the camera
-    let mut view_matrix = Mat4::zero();
-    let mut projection_matrix = Mat4::zero();
-    for (transform, camera) in &mut camera_query.iter() {
-        view_matrix = transform.value.inverse();
-        projection_matrix = camera.projection_matrix;
+/// Get the vertex indices of a mesh. Meshes without indices are treated as if every vertex is
+/// referenced once in order, so a non-indexed `TriangleList` uses each consecutive triplet of
+/// vertices as a triangle.
+fn mesh_indices(mesh: &Mesh, vertex_count: usize) -> Cow<[u32]> {
+    match &mesh.indices {
+        Some(indices) => Cow::Borrowed(indices),
+        None => Cow::Owned((0..vertex_count as u32).collect()),
     }
+}
 
-    // After initial checks completed, clear the pick list
-    pick_state.ordered_pick_list.clear();
+/// Checks that every index refers to one of the mesh's vertices
+fn validate_indices(indices: &[u32], vertex_count: usize) -> Result<(), PickError> {
+    match indices.iter().find(|index| **index as usize >= vertex_count) {
+        Some(index) => Err(PickError::IndexOutOfBounds {
+            index: *index,
+            vertex_count,
+        }),
+        None => Ok(()),
+    }
+}
 
-    // Iterate through each selectable mesh in the scene
-    for (mesh_handle, transform, mut pickable, entity) in &mut mesh_query.iter() {
-        // Use the mesh handle to get a reference to a mesh asset
-        if let Some(mesh) = meshes.get(mesh_handle) {
-            if mesh.primitive_topology != PrimitiveTopology::TriangleList {
-                continue;
-            }
+/// The primitives of a mesh, as indices into the mesh's vertex positions
+enum MeshPrimitives {
+    Triangles(Vec<[u32; 3]>),
+    Lines(Vec<[u32; 2]>),
+    Points(Vec<u32>),
+}
 
-            // The ray cast can hit the same mesh many times, so we need to track which hit is
-            // closest to the camera, and record that.
-            let mut hit_depth = f32::MAX;
-
-            // We need to transform the mesh vertices' positions from the mesh space to the world
-            // space using the mesh's transform, move it to the camera's space using the view
-            // matrix (camera.inverse), and finally, apply the projection matrix. Because column
-            // matrices are evaluated right to left, we have to order it correctly:
-            let mesh_to_cam_transform = view_matrix * transform.value;
-
-            // Get the vertex positions from the mesh reference resolved from the mesh handle
-            let vertex_positions: Vec<[f32; 3]> = mesh.attributes.iter()
-                .filter(|attribute| attribute.name == VertexAttribute::POSITION)
-                .filter_map(|attribute| match &attribute.values {
-                    VertexAttributeValues::Float3(positions) => Some(positions.clone()),
-                    _ => panic!("Unexpected vertex types in VertexAttribute::POSITION"),
-                }).last().unwrap();
-
-            /*
-            let mut vertex_positions = Vec::new();
-            for attribute in mesh.attributes.iter() {
-                if attribute.name == VertexAttribute::POSITION {
-                    vertex_positions = match &attribute.values {
-                        VertexAttributeValues::Float3(positions) => positions.clone(),
-                        _ => panic!("Unexpected vertex types in VertexAttribute::POSITION"),
-                    };
-                }
-            }*/
-
-            // We have everything set up, now we can jump into the mesh's list of indices and
-            // check triangles for cursor intersection.
-            if let Some(indices) = &mesh.indices {
-                let mut hit_found = false;
-                // Now that we're in the vector of vertex indices, we want to look at the vertex
-                // positions for each triangle, so we'll take indices in chunks of three, where each
-                // chunk of three indices are references to the three vertices of a triangle.
-                for index in indices.chunks(3) {
-                    // Make sure this chunk has 3 vertices to avoid a panic.
-                    if index.len() == 3 {
-                        // Set up an empty container for triangle vertices
-                        let mut triangle: [Vec3; 3] = [Vec3::zero(), Vec3::zero(), Vec3::zero()];
-                        // We can now grab the position of each vertex in the triangle using the
-                        // indices pointing into the position vector. These positions are relative
-                        // to the coordinate system of the mesh the vertex/triangle belongs to. To
-                        // test if the triangle is being hovered over, we need to convert this to
-                        // NDC (normalized device coordinates)
-                        for i in 0..3 {
-                            // Get the raw vertex position using the index
-                            let mut vertex_pos = Vec3::from(vertex_positions[index[i] as usize]);
-                            // Transform the vertex to world space with the mesh transform, then
-                            // into camera space with the view transform.
-                            vertex_pos = mesh_to_cam_transform.transform_point3(vertex_pos);
-                            // This next part seems to be a bug with glam - it should do the divide
-                            // by w perspective math for us, instead we have to do it manually.
-                            // `glam` PR https://github.com/bitshifter/glam-rs/pull/75/files
-                            let transformed = projection_matrix.mul_vec4(vertex_pos.extend(1.0));
-                            let w = transformed.w();
-                            triangle[i] = Vec3::from(transformed.truncate() / w);
-                        }
-                        if point_in_tri(
-                            &cursor_pos_ndc,
-                            &Vec2::new(triangle[0].x(), triangle[0].y()),
-                            &Vec2::new(triangle[1].x(), triangle[1].y()),
-                            &Vec2::new(triangle[2].x(), triangle[2].y()),
-                        ) {
-                            hit_found = true;
-                            if  triangle[0].z() < hit_depth {
-                                hit_depth = triangle[0].z();
-                                //println!("hit depth: {}", hit_depth);
-                                // if the hovered mesh has changed, update the pick state
-                            }
-                        }
+/// Decodes the primitives of a mesh from its vertex indices according to its topology. Triangle
+/// and line strips are expanded into individual triangles and line segments. Returns an error if
+/// any index is out of bounds, so the primitives can be used to index the vertex positions.
+fn mesh_primitives(mesh: &Mesh, vertex_count: usize) -> Result<MeshPrimitives, PickError> {
+    let indices = mesh_indices(mesh, vertex_count);
+    validate_indices(&indices, vertex_count)?;
+    Ok(match mesh.primitive_topology {
+        PrimitiveTopology::TriangleList => MeshPrimitives::Triangles(
+            indices
+                .chunks(3)
+                .filter(|index| index.len() == 3)
+                .map(|index| [index[0], index[1], index[2]])
+                .collect(),
+        ),
+        // Every other triangle in a strip has reversed winding, swap two vertices to keep the
+        // winding, and therefore the face normal, consistent.
+        PrimitiveTopology::TriangleStrip => MeshPrimitives::Triangles(
+            indices
+                .windows(3)
+                .enumerate()
+                .map(|(i, index)| {
+                    if i % 2 == 0 {
+                        [index[0], index[1], index[2]]
+                    } else {
+                        [index[1], index[0], index[2]]
                     }
-                }
+                })
+                .collect(),
+        ),
+        PrimitiveTopology::LineList => MeshPrimitives::Lines(
+            indices
+                .chunks(2)
+                .filter(|index| index.len() == 2)
+                .map(|index| [index[0], index[1]])
+                .collect(),
+        ),
+        PrimitiveTopology::LineStrip => MeshPrimitives::Lines(
+            indices
+                .windows(2)
+                .map(|index| [index[0], index[1]])
+                .collect(),
+        ),
+        PrimitiveTopology::PointList => MeshPrimitives::Points(indices.to_vec()),
+    })
+}
 
-                pickable.picked = hit_found;
-                if hit_found {
-                    pick_state.ordered_pick_list.push(
-                        PickDepth::new(entity, hit_depth)
-                    );
-                }
+/// Get the vertex normals of a mesh, in the mesh's coordinate system, if it has a normal for each
+/// of its `vertex_count` vertices
+fn mesh_vertex_normals(mesh: &Mesh, vertex_count: usize) -> Option<Vec<[f32; 3]>> {
+    mesh.attributes.iter()
+        .filter(|attribute| attribute.name == VertexAttribute::NORMAL)
+        .filter_map(|attribute| match &attribute.values {
+            VertexAttributeValues::Float3(normals) => Some(normals.clone()),
+            _ => None,
+        }).last()
+        .filter(|normals| normals.len() == vertex_count)
+}
 
-            } else {
-                panic!(
-                    "No index matrix found in mesh {:?}\n{:?}",
-                    mesh_handle, mesh
-                );
-            }
-        }
-    }
+/// Transforms a point with the supplied model-view-projection matrix and returns its position in
+/// normalized device coordinates, along with the clip space w. A w less than or equal to zero
+/// means the point is behind a perspective camera, and its NDC position is not meaningful. With an
+/// orthographic camera w is always one, and points behind the camera have a negative NDC depth.
+fn project_to_ndc(mesh_to_ndc: &Mat4, point: Vec3) -> (Vec3, f32) {
+    // This seems to be a bug with glam - `transform_point3` should do the divide by w perspective
+    // math for us, instead we have to do it manually.
+    // `glam` PR https://github.com/bitshifter/glam-rs/pull/75/files
+    let transformed = mesh_to_ndc.mul_vec4(point.extend(1.0));
+    let w = transformed.w();
+    (Vec3::from(transformed.truncate() / w), w)
+}
 
-    // Sort the pick list
-    pick_state
-        .ordered_pick_list
-        .sort_by(|a, b| a.partial_cmp(b)
-        .unwrap_or(std::cmp::Ordering::Equal));
+/// Checks if a projection matrix is orthographic. Perspective projections copy the view space depth
+/// into w, so the bottom right element of the matrix is zero, while orthographic projections leave
+/// w as one.
+fn is_orthographic(projection_matrix: &Mat4) -> bool {
+    projection_matrix.w_axis().w() != 0.0
 }
 
-/// Compute the area of a triangle given 2D vertex coordinates, "/2" removed to save an operation
-fn double_tri_area(a: &Vec2, b: &Vec2, c: &Vec2) -> f32 {
-    f32::abs(a.x() * (b.y() - c.y()) + b.x() * (c.y() - a.y()) + c.x() * (a.y() - b.y()))
+/// Checks if two 2D line segments `a`-`b` and `c`-`d` intersect
+fn segments_intersect(a: &Vec2, b: &Vec2, c: &Vec2, d: &Vec2) -> bool {
+    let cross = |o: &Vec2, p: &Vec2, q: &Vec2| {
+        (p.x() - o.x()) * (q.y() - o.y()) - (p.y() - o.y()) * (q.x() - o.x())
+    };
+    let d1 = cross(c, d, a);
+    let d2 = cross(c, d, b);
+    let d3 = cross(a, b, c);
+    let d4 = cross(a, b, d);
+    ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0))
+        && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0))
 }
 
-/// Checks if a point is inside a triangle by comparing the summed areas of the triangles, the point
-/// is inside the triangle if the areas are equal. An epsilon is used due to floating point error.
-/// Todo: barycentric method
+/// Computes the barycentric coordinates of a point with respect to a 2D triangle, or `None` if the
+/// triangle is degenerate. Each coordinate is the weight of the matching vertex; the point is
+/// inside the triangle if none of them are negative.
+fn barycentric(p: &Vec2, a: &Vec2, b: &Vec2, c: &Vec2) -> Option<Vec3> {
+    let v0 = *b - *a;
+    let v1 = *c - *a;
+    let v2 = *p - *a;
+    let denominator = v0.x() * v1.y() - v1.x() * v0.y();
+    if denominator == 0.0 {
+        return None;
+    }
+    let v = (v2.x() * v1.y() - v1.x() * v2.y()) / denominator;
+    let w = (v0.x() * v2.y() - v2.x() * v0.y()) / denominator;
+    Some(Vec3::new(1.0 - v - w, v, w))
+}
+
+/// Checks if a point is inside a triangle using its barycentric coordinates
 fn point_in_tri(p: &Vec2, a: &Vec2, b: &Vec2, c: &Vec2) -> bool {
-    let area = double_tri_area(a, b, c);
-    let pab = double_tri_area(p, a, b);
-    let pac = double_tri_area(p, a, c);
-    let pbc = double_tri_area(p, b, c);
-    let area_tris = pab + pac + pbc;
-    let epsilon = 0.000001;
-    //println!("{:.3}  {:.3}", area, area_tris);
-    f32::abs(area - area_tris) < epsilon
+    match barycentric(p, a, b, c) {
+        Some(b) => b.x() >= 0.0 && b.y() >= 0.0 && b.z() >= 0.0,
+        None => false,
+    }
+}
+
+/// Checks if a point is inside a polygon using the even-odd rule: a ray cast from the point in the
+/// +x direction crosses the polygon's edges an odd number of times if the point is inside. This
+/// also gives a sensible result for self-intersecting polygons, such as a hand drawn lasso.
+fn point_in_poly(p: &Vec2, polygon: &[Vec2]) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len().wrapping_sub(1);
+    for i in 0..polygon.len() {
+        let (a, b) = (&polygon[i], &polygon[j]);
+        if (a.y() > p.y()) != (b.y() > p.y())
+            && p.x() < (b.x() - a.x()) * (p.y() - a.y()) / (b.y() - a.y()) + a.x()
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Spawns entities for tests that only need distinct ids
+#[cfg(test)]
+fn test_entities(count: usize) -> Vec<Entity> {
+    let mut world = World::new();
+    (0..count).map(|_| world.spawn((0u32,))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh(primitive_topology: PrimitiveTopology, vertex_count: usize) -> Mesh {
+        Mesh {
+            primitive_topology,
+            attributes: vec![VertexAttribute::position(
+                (0..vertex_count).map(|i| [i as f32, 0.0, 0.0]).collect(),
+            )],
+            indices: None,
+        }
+    }
+
+    #[test]
+    fn triangle_strip_keeps_winding() {
+        let mesh = mesh(PrimitiveTopology::TriangleStrip, 5);
+        match mesh_primitives(&mesh, 5).unwrap() {
+            // The second triangle has its first two vertices swapped
+            MeshPrimitives::Triangles(triangles) => {
+                assert_eq!(triangles, vec![[0, 1, 2], [2, 1, 3], [2, 3, 4]])
+            }
+            _ => panic!("Triangle strip wasn't decoded into triangles"),
+        }
+    }
+
+    #[test]
+    fn line_strip_is_split_into_segments() {
+        let mut mesh = mesh(PrimitiveTopology::LineStrip, 4);
+        mesh.indices = Some(vec![3, 1, 0, 2]);
+        match mesh_primitives(&mesh, 4).unwrap() {
+            MeshPrimitives::Lines(lines) => assert_eq!(lines, vec![[3, 1], [1, 0], [0, 2]]),
+            _ => panic!("Line strip wasn't decoded into lines"),
+        }
+    }
+
+    #[test]
+    fn out_of_bounds_index_is_an_error() {
+        let mut mesh = mesh(PrimitiveTopology::TriangleList, 3);
+        mesh.indices = Some(vec![0, 1, 3]);
+        assert_eq!(
+            mesh_primitives(&mesh, 3).err(),
+            Some(PickError::IndexOutOfBounds {
+                index: 3,
+                vertex_count: 3
+            })
+        );
+    }
+
+    #[test]
+    fn point_in_self_intersecting_lasso() {
+        // A bow tie, whose edges cross at (1, 1)
+        let bow_tie = [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(2.0, 2.0),
+            Vec2::new(2.0, 0.0),
+            Vec2::new(0.0, 2.0),
+        ];
+        assert!(point_in_poly(&Vec2::new(0.3, 1.0), &bow_tie));
+        assert!(point_in_poly(&Vec2::new(1.7, 1.0), &bow_tie));
+        assert!(!point_in_poly(&Vec2::new(1.0, 0.3), &bow_tie));
+        assert!(!point_in_poly(&Vec2::new(1.0, 1.7), &bow_tie));
+        assert!(!point_in_poly(&Vec2::new(3.0, 1.0), &bow_tie));
+
+        // A pentagram loops around its center twice, so with the even-odd rule the center is
+        // outside while the points of the star are inside
+        let pentagram: Vec<Vec2> = [0, 2, 4, 1, 3]
+            .iter()
+            .map(|i| {
+                let angle = (90.0 + 72.0 * *i as f32).to_radians();
+                Vec2::new(angle.cos(), angle.sin())
+            })
+            .collect();
+        assert!(!point_in_poly(&Vec2::zero(), &pentagram));
+        assert!(point_in_poly(&Vec2::new(0.0, 0.8), &pentagram));
+        assert!(!point_in_poly(&Vec2::new(0.0, 1.2), &pentagram));
+    }
 }
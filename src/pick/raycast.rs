@@ -0,0 +1,654 @@
+use super::*;
+
+/// A ray in 3D space, defined by an origin and a direction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray3d {
+    origin: Vec3,
+    direction: Vec3,
+}
+
+impl Ray3d {
+    /// Creates a new ray. The direction is normalized, so distances along the ray are in the same
+    /// units as the origin.
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Ray3d {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    pub fn origin(&self) -> Vec3 {
+        self.origin
+    }
+
+    pub fn direction(&self) -> Vec3 {
+        self.direction
+    }
+
+    /// The point at `distance` along the ray
+    pub fn position(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    /// Creates a ray from the camera through the cursor, given the cursor position in NDC, the
+    /// camera's transform, and its projection matrix. Perspective rays start at the camera and
+    /// fan out through the cursor. Orthographic rays are parallel to the camera's view direction,
+    /// and start where the cursor is on the near plane, so distances are measured from the near
+    /// plane.
+    pub fn from_screenspace(
+        cursor_pos_ndc: Vec2,
+        camera_transform: &Mat4,
+        projection_matrix: &Mat4,
+    ) -> Self {
+        // Unproject the cursor at the near (NDC z = 0) and far (NDC z = 1) planes back into world
+        // space to find two points the ray passes through. The divide by w is done manually for
+        // the same reason as in `project_to_ndc`.
+        let ndc_to_world = *camera_transform * projection_matrix.inverse();
+        let unproject = |depth: f32| {
+            let point = ndc_to_world.mul_vec4(cursor_pos_ndc.extend(depth).extend(1.0));
+            point.truncate() / point.w()
+        };
+        let cursor_far = unproject(1.0);
+        let origin = if is_orthographic(projection_matrix) {
+            unproject(0.0)
+        } else {
+            camera_transform.w_axis().truncate()
+        };
+        Ray3d::new(origin, cursor_far - origin)
+    }
+
+    /// Transforms the ray into another coordinate system. The direction is deliberately not
+    /// normalized, so that a distance along the transformed ray is also the distance along the
+    /// original ray.
+    fn transform(&self, matrix: &Mat4) -> Self {
+        Ray3d {
+            origin: matrix.transform_point3(self.origin),
+            direction: matrix.transform_vector3(self.direction),
+        }
+    }
+}
+
+/// How far from a ray, in world units, a line or point can be and still be hit. The tolerance is
+/// set in screen pixels, so for perspective cameras it grows with the distance from the camera.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PickTolerance {
+    base: f32,
+    per_distance: f32,
+}
+
+impl PickTolerance {
+    /// Creates a tolerance that only hits lines and points the ray passes exactly through
+    pub fn none() -> Self {
+        PickTolerance::default()
+    }
+
+    /// Converts a tolerance in pixels into world units for a camera's projection matrix, given the
+    /// height of the viewport in pixels.
+    pub fn from_screenspace(pixels: f32, projection_matrix: &Mat4, viewport_height: f32) -> Self {
+        // The projection maps the height of the view to 2 NDC units, scaled by the y axis of the
+        // projection. For a perspective projection this is the height at a distance of 1.
+        let world_per_pixel = 2.0 / (projection_matrix.y_axis().y() * viewport_height);
+        if is_orthographic(projection_matrix) {
+            PickTolerance {
+                base: pixels * world_per_pixel,
+                per_distance: 0.0,
+            }
+        } else {
+            PickTolerance {
+                base: 0.0,
+                per_distance: pixels * world_per_pixel,
+            }
+        }
+    }
+
+    /// The tolerance in world units at a distance along the ray
+    pub fn at_distance(&self, distance: f32) -> f32 {
+        self.base + self.per_distance * distance
+    }
+}
+
+/// Options for casting a ray into the scene with `cast_ray`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayCastOptions {
+    /// How close to the ray lines and points must be to be hit
+    pub tolerance: PickTolerance,
+    /// Only entities on at least one of these layers can be hit
+    pub layers: PickLayers,
+    /// Skip hits that fail the `PickAlphaTest` of the entity that was hit
+    pub alpha_test: bool,
+}
+
+impl Default for RayCastOptions {
+    fn default() -> Self {
+        RayCastOptions {
+            tolerance: PickTolerance::default(),
+            layers: PickLayers::default(),
+            alpha_test: true,
+        }
+    }
+}
+
+/// Casts a ray against every `PickableMesh` in the query, and returns all hits sorted by distance
+/// from the ray origin, nearest first. Each entity is hit at most once, at its nearest primitive.
+/// Lines and points are hit if they are within the tolerance of the ray, and entities that aren't
+/// on any of the options' layers are ignored. Primitives of `PickAlphaTest` entities are skipped
+/// where they are transparent, so the ray continues to the primitives and meshes behind them, the
+/// same as for the cursor.
+///
+/// This can be used from any system to cast arbitrary rays, for example from a gizmo or an entity,
+/// by adding `Res<Assets<Mesh>>`, `Res<Assets<StandardMaterial>>`, `Res<Assets<Texture>>`,
+/// `Res<PickBvhCache>`, `Res<PickSceneIndex>` and a query matching the one below to the system's
+/// parameters.
+pub fn cast_ray(
+    ray: &Ray3d,
+    options: &RayCastOptions,
+    meshes: &Assets<Mesh>,
+    alpha_test_assets: &AlphaTestAssets,
+    bvh_cache: &PickBvhCache,
+    scene_index: &PickSceneIndex,
+    query: &Query<(&Handle<Mesh>, &Transform, &PickableMesh)>,
+) -> Vec<PickDepth> {
+    let tolerance = &options.tolerance;
+    let mut hits = Vec::new();
+    // Only entities whose boxes in the scene index are hit by the ray need to be tested
+    for entity in scene_index.ray_candidates(ray, tolerance) {
+        let (mesh_handle, transform, pickable) = match (
+            query.get::<Handle<Mesh>>(entity),
+            query.get::<Transform>(entity),
+            query.get::<PickableMesh>(entity),
+        ) {
+            (Ok(mesh_handle), Ok(transform), Ok(pickable)) => (mesh_handle, transform, pickable),
+            _ => continue,
+        };
+        let layers = match query.get::<PickLayers>(entity) {
+            Ok(layers) => *layers,
+            Err(_) => PickLayers::default(),
+        };
+        if !layers.intersects(&options.layers) {
+            continue;
+        }
+        // Skip the per-primitive test for meshes the ray can't possibly hit
+        if !pickable.bounding_sphere.intersects_ray(ray, tolerance) {
+            continue;
+        }
+        // Use the mesh handle to get a reference to a mesh asset
+        if let Some(mesh) = meshes.get(&mesh_handle) {
+            let alpha_tester = if options.alpha_test {
+                AlphaTester::new(entity, mesh, alpha_test_assets, query)
+            } else {
+                None
+            };
+            if let Some(hit) = ray_mesh_intersection(
+                ray,
+                tolerance,
+                mesh,
+                bvh_cache.get(&mesh_handle),
+                alpha_tester.as_ref(),
+                &transform.value,
+                entity,
+            ) {
+                hits.push(hit);
+            }
+        }
+    }
+    hits.sort_by(|a, b| {
+        a.distance
+            .partial_cmp(&b.distance)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    hits
+}
+
+/// Casts a ray against the primitives of a mesh and returns the closest hit, if any. Rather than
+/// transforming every vertex of the mesh into world space, the ray is transformed into the mesh's
+/// coordinate system to test triangles. If the mesh's primitives have been cached, triangles are
+/// found with the mesh's BVH, and the mesh is never read. Otherwise the mesh is decoded and every
+/// triangle is tested. Lines and points are tested in world space, because the tolerance is a
+/// world space distance. Primitives that fail the alpha test are skipped, so the closest hit that
+/// passes is returned.
+pub(crate) fn ray_mesh_intersection(
+    ray: &Ray3d,
+    tolerance: &PickTolerance,
+    mesh: &Mesh,
+    cached_primitives: Option<&CachedMeshPrimitives>,
+    alpha_tester: Option<&AlphaTester>,
+    mesh_to_world: &Mat4,
+    entity: Entity,
+) -> Option<PickDepth> {
+    match cached_primitives {
+        Some(CachedMeshPrimitives::Triangles(bvh)) => {
+            let mesh_ray = ray.transform(&mesh_to_world.inverse());
+            let (distance, triangle_index, barycentric) = bvh.intersect(&mesh_ray, alpha_tester)?;
+            let normal = bvh.normal(triangle_index, barycentric);
+            return Some(triangle_pick_depth(
+                ray,
+                mesh_to_world,
+                entity,
+                distance,
+                triangle_index,
+                barycentric,
+                normal,
+            ));
+        }
+        Some(CachedMeshPrimitives::Lines { positions, lines }) => {
+            return ray_lines_intersection(
+                ray,
+                tolerance,
+                positions,
+                lines,
+                alpha_tester,
+                mesh_to_world,
+                entity,
+            );
+        }
+        Some(CachedMeshPrimitives::Points { positions, points }) => {
+            return ray_points_intersection(
+                ray,
+                tolerance,
+                positions,
+                points,
+                alpha_tester,
+                mesh_to_world,
+                entity,
+            );
+        }
+        None => {}
+    }
+
+    // Invalid meshes are reported when their bounds are computed, here they are just skipped
+    let vertex_positions: Vec<Vec3> = mesh_vertex_positions(mesh)
+        .ok()?
+        .into_iter()
+        .map(Vec3::from)
+        .collect();
+    let triangles = match mesh_primitives(mesh, vertex_positions.len()).ok()? {
+        MeshPrimitives::Triangles(triangles) => triangles,
+        MeshPrimitives::Lines(lines) => {
+            return ray_lines_intersection(
+                ray,
+                tolerance,
+                &vertex_positions,
+                &lines,
+                alpha_tester,
+                mesh_to_world,
+                entity,
+            );
+        }
+        MeshPrimitives::Points(points) => {
+            return ray_points_intersection(
+                ray,
+                tolerance,
+                &vertex_positions,
+                &points,
+                alpha_tester,
+                mesh_to_world,
+                entity,
+            );
+        }
+    };
+    let mesh_ray = ray.transform(&mesh_to_world.inverse());
+
+    // The ray can hit the same mesh many times, so we need to track which hit is closest to the
+    // ray origin, and record that.
+    let mut closest_hit: Option<(f32, usize, Vec3)> = None;
+    for (triangle_index, index) in triangles.iter().enumerate() {
+        let triangle = [
+            vertex_positions[index[0] as usize],
+            vertex_positions[index[1] as usize],
+            vertex_positions[index[2] as usize],
+        ];
+        if let Some((distance, barycentric)) = ray_triangle_intersection(&mesh_ray, &triangle) {
+            if closest_hit.map_or(true, |(closest, ..)| distance < closest)
+                && alpha_tester.map_or(true, |tester| tester.passes(index, barycentric))
+            {
+                closest_hit = Some((distance, triangle_index, barycentric));
+            }
+        }
+    }
+
+    let (distance, triangle_index, barycentric) = closest_hit?;
+    let index = &triangles[triangle_index];
+    let normal = match mesh_vertex_normals(mesh, vertex_positions.len()) {
+        Some(normals) => {
+            Vec3::from(normals[index[0] as usize]) * barycentric.x()
+                + Vec3::from(normals[index[1] as usize]) * barycentric.y()
+                + Vec3::from(normals[index[2] as usize]) * barycentric.z()
+        }
+        None => {
+            let a = vertex_positions[index[0] as usize];
+            let b = vertex_positions[index[1] as usize];
+            let c = vertex_positions[index[2] as usize];
+            (b - a).cross(c - a)
+        }
+    };
+    Some(triangle_pick_depth(
+        ray,
+        mesh_to_world,
+        entity,
+        distance,
+        triangle_index,
+        barycentric,
+        normal,
+    ))
+}
+
+/// Finds the closest line of a mesh within the tolerance of a ray. `positions` are the mesh's
+/// vertex positions, in its coordinate system.
+fn ray_lines_intersection(
+    ray: &Ray3d,
+    tolerance: &PickTolerance,
+    positions: &[Vec3],
+    lines: &[[u32; 2]],
+    alpha_tester: Option<&AlphaTester>,
+    mesh_to_world: &Mat4,
+    entity: Entity,
+) -> Option<PickDepth> {
+    let world_position = |index: u32| mesh_to_world.transform_point3(positions[index as usize]);
+    let mut closest_hit: Option<(f32, usize, Vec3)> = None;
+    for (line_index, line) in lines.iter().enumerate() {
+        let segment = [world_position(line[0]), world_position(line[1])];
+        if let Some((distance, s)) = ray_segment_intersection(ray, &segment, tolerance) {
+            let barycentric = Vec3::new(1.0 - s, s, 0.0);
+            if closest_hit.map_or(true, |(closest, ..)| distance < closest)
+                && alpha_tester.map_or(true, |tester| tester.passes(line, barycentric))
+            {
+                closest_hit = Some((distance, line_index, barycentric));
+            }
+        }
+    }
+    let (distance, line_index, barycentric) = closest_hit?;
+    Some(PickDepth {
+        entity,
+        distance,
+        position: ray.position(distance),
+        normal: -ray.direction(),
+        primitive_index: line_index,
+        barycentric,
+    })
+}
+
+/// Finds the closest point of a mesh within the tolerance of a ray. `positions` are the mesh's
+/// vertex positions, in its coordinate system.
+fn ray_points_intersection(
+    ray: &Ray3d,
+    tolerance: &PickTolerance,
+    positions: &[Vec3],
+    points: &[u32],
+    alpha_tester: Option<&AlphaTester>,
+    mesh_to_world: &Mat4,
+    entity: Entity,
+) -> Option<PickDepth> {
+    let mut closest_hit: Option<(f32, usize)> = None;
+    for (point_index, index) in points.iter().enumerate() {
+        let point = mesh_to_world.transform_point3(positions[*index as usize]);
+        if let Some(distance) = ray_point_intersection(ray, point, tolerance) {
+            if closest_hit.map_or(true, |(closest, _)| distance < closest)
+                && alpha_tester.map_or(true, |tester| {
+                    tester.passes(&[*index], Vec3::new(1.0, 0.0, 0.0))
+                })
+            {
+                closest_hit = Some((distance, point_index));
+            }
+        }
+    }
+    let (distance, point_index) = closest_hit?;
+    Some(PickDepth {
+        entity,
+        distance,
+        position: ray.position(distance),
+        normal: -ray.direction(),
+        primitive_index: point_index,
+        barycentric: Vec3::new(1.0, 0.0, 0.0),
+    })
+}
+
+/// Describes a hit on a triangle of a mesh. The normal is in the mesh's coordinate system.
+fn triangle_pick_depth(
+    ray: &Ray3d,
+    mesh_to_world: &Mat4,
+    entity: Entity,
+    distance: f32,
+    triangle_index: usize,
+    barycentric: Vec3,
+    normal: Vec3,
+) -> PickDepth {
+    PickDepth {
+        entity,
+        distance,
+        position: ray.position(distance),
+        // Normals are transformed with the inverse transpose of the mesh transform, so they stay
+        // perpendicular to non-uniformly scaled surfaces.
+        normal: mesh_to_world
+            .inverse()
+            .transpose()
+            .transform_vector3(normal)
+            .normalize(),
+        primitive_index: triangle_index,
+        barycentric,
+    }
+}
+
+/// Finds the closest approach between a ray and a line segment. If the segment comes within the
+/// tolerance of the ray, returns the distance along the ray and the fraction of the way along the
+/// segment where they are closest. Segments behind the ray origin are ignored.
+fn ray_segment_intersection(
+    ray: &Ray3d,
+    segment: &[Vec3; 2],
+    tolerance: &PickTolerance,
+) -> Option<(f32, f32)> {
+    let segment_direction = segment[1] - segment[0];
+    let to_segment = ray.origin - segment[0];
+    let a = ray.direction.dot(ray.direction);
+    let b = ray.direction.dot(segment_direction);
+    let c = segment_direction.dot(segment_direction);
+    let d = ray.direction.dot(to_segment);
+    let e = segment_direction.dot(to_segment);
+    let denominator = a * c - b * b;
+
+    // Closest point on the segment, ignoring the ray origin. Parallel lines (and segments with no
+    // length) are closest at the start of the segment.
+    let mut s = if denominator > f32::EPSILON * a * c {
+        ((a * e - b * d) / denominator).max(0.0).min(1.0)
+    } else {
+        0.0
+    };
+    let mut distance = (s * b - d) / a;
+    // The closest point is behind the ray, so the ray origin is the closest point on the ray
+    if distance < 0.0 {
+        distance = 0.0;
+        s = if c > 0.0 {
+            (e / c).max(0.0).min(1.0)
+        } else {
+            0.0
+        };
+    }
+    let separation = (ray.position(distance) - (segment[0] + segment_direction * s)).length();
+    if separation <= tolerance.at_distance(distance) {
+        Some((distance, s))
+    } else {
+        None
+    }
+}
+
+/// Checks if a point is within the tolerance of a ray, and returns the distance along the ray to
+/// the point closest to it. Points behind the ray origin are ignored.
+fn ray_point_intersection(ray: &Ray3d, point: Vec3, tolerance: &PickTolerance) -> Option<f32> {
+    let distance = (point - ray.origin).dot(ray.direction);
+    if distance < 0.0 {
+        return None;
+    }
+    let separation = (ray.position(distance) - point).length();
+    if separation <= tolerance.at_distance(distance) {
+        Some(distance)
+    } else {
+        None
+    }
+}
+
+/// Intersects a ray with a triangle using the Möller–Trumbore algorithm. Returns the distance
+/// along the ray, in units of the ray's direction, and the barycentric coordinates of the hit.
+/// Triangles are hit from both sides, and hits behind the ray origin are ignored.
+pub(crate) fn ray_triangle_intersection(ray: &Ray3d, triangle: &[Vec3; 3]) -> Option<(f32, Vec3)> {
+    let edge_1 = triangle[1] - triangle[0];
+    let edge_2 = triangle[2] - triangle[0];
+    let p = ray.direction.cross(edge_2);
+    let determinant = edge_1.dot(p);
+    // The ray is parallel to the triangle
+    if determinant == 0.0 {
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+
+    let s = ray.origin - triangle[0];
+    let u = s.dot(p) * inverse_determinant;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+    let q = s.cross(edge_1);
+    let v = ray.direction.dot(q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = edge_2.dot(q) * inverse_determinant;
+    if distance <= 0.0 {
+        return None;
+    }
+    Some((distance, Vec3::new(1.0 - u - v, u, v)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle() -> [Vec3; 3] {
+        [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ]
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    fn perspective() -> Mat4 {
+        // A 90 degree vertical field of view, twice as wide as it is high
+        Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 2.0, 0.1, 10.0)
+    }
+
+    fn orthographic() -> Mat4 {
+        Mat4::orthographic_rh(-4.0, 4.0, -2.0, 2.0, 1.0, 10.0)
+    }
+
+    fn camera_transform() -> Mat4 {
+        Mat4::from_translation(Vec3::new(0.0, 0.0, 5.0))
+    }
+
+    #[test]
+    fn perspective_ray_from_screenspace() {
+        let center = Ray3d::from_screenspace(Vec2::zero(), &camera_transform(), &perspective());
+        assert_close(center.origin(), Vec3::new(0.0, 0.0, 5.0));
+        assert_close(center.direction(), Vec3::new(0.0, 0.0, -1.0));
+        let corner =
+            Ray3d::from_screenspace(Vec2::new(1.0, 1.0), &camera_transform(), &perspective());
+        assert_close(corner.origin(), Vec3::new(0.0, 0.0, 5.0));
+        assert_close(corner.direction(), Vec3::new(2.0, 1.0, -1.0).normalize());
+    }
+
+    #[test]
+    fn orthographic_ray_from_screenspace() {
+        let center = Ray3d::from_screenspace(Vec2::zero(), &camera_transform(), &orthographic());
+        assert_close(center.origin(), Vec3::new(0.0, 0.0, 4.0));
+        assert_close(center.direction(), Vec3::new(0.0, 0.0, -1.0));
+        let corner =
+            Ray3d::from_screenspace(Vec2::new(1.0, 1.0), &camera_transform(), &orthographic());
+        assert_close(corner.origin(), Vec3::new(4.0, 2.0, 4.0));
+        // Orthographic rays are parallel
+        assert_close(corner.direction(), center.direction());
+    }
+
+    #[test]
+    fn perspective_tolerance_from_screenspace() {
+        // The view is 2 units high at a distance of 1, so each of the 200 pixels is 0.01 units
+        let tolerance = PickTolerance::from_screenspace(3.0, &perspective(), 200.0);
+        assert!(tolerance.at_distance(0.0).abs() < 1e-6);
+        assert!((tolerance.at_distance(1.0) - 0.03).abs() < 1e-6);
+        assert!((tolerance.at_distance(10.0) - 0.3).abs() < 1e-5);
+    }
+
+    #[test]
+    fn orthographic_tolerance_from_screenspace() {
+        // The view is 4 units high at every distance, so each of the 200 pixels is 0.02 units
+        let tolerance = PickTolerance::from_screenspace(3.0, &orthographic(), 200.0);
+        assert!((tolerance.at_distance(0.0) - 0.06).abs() < 1e-6);
+        assert!((tolerance.at_distance(10.0) - 0.06).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ray_triangle_hit() {
+        let ray = Ray3d::new(Vec3::new(0.25, 0.25, -2.0), Vec3::new(0.0, 0.0, 1.0));
+        let (distance, barycentric) = ray_triangle_intersection(&ray, &triangle()).unwrap();
+        assert!((distance - 2.0).abs() < 1e-5);
+        assert_close(barycentric, Vec3::new(0.5, 0.25, 0.25));
+    }
+
+    #[test]
+    fn ray_triangle_hit_from_behind() {
+        let ray = Ray3d::new(Vec3::new(0.25, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let (distance, _) = ray_triangle_intersection(&ray, &triangle()).unwrap();
+        assert!((distance - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ray_triangle_miss() {
+        let ray = Ray3d::new(Vec3::new(0.75, 0.75, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(ray_triangle_intersection(&ray, &triangle()), None);
+    }
+
+    #[test]
+    fn ray_triangle_behind_origin() {
+        let ray = Ray3d::new(Vec3::new(0.25, 0.25, 1.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(ray_triangle_intersection(&ray, &triangle()), None);
+    }
+
+    #[test]
+    fn ray_triangle_parallel() {
+        let ray = Ray3d::new(Vec3::new(-1.0, 0.25, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(ray_triangle_intersection(&ray, &triangle()), None);
+        let ray = Ray3d::new(Vec3::new(-1.0, 0.25, -1.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(ray_triangle_intersection(&ray, &triangle()), None);
+    }
+
+    #[test]
+    fn cached_lines_and_points_match_the_mesh() {
+        let entity = test_entities(1)[0];
+        let tolerance = PickTolerance::from_screenspace(5.0, &perspective(), 200.0);
+        let ray = Ray3d::new(Vec3::new(1.02, 0.0, -3.0), Vec3::new(0.0, 0.0, 1.0));
+        let topologies = [PrimitiveTopology::LineStrip, PrimitiveTopology::PointList];
+        for primitive_topology in topologies.iter() {
+            let mesh = Mesh {
+                primitive_topology: *primitive_topology,
+                attributes: vec![VertexAttribute::position(
+                    (0..4).map(|i| [i as f32, 0.0, 0.0]).collect(),
+                )],
+                indices: None,
+            };
+            let cached = CachedMeshPrimitives::new(&mesh);
+            let mesh_to_world = Mat4::identity();
+            let expected =
+                ray_mesh_intersection(&ray, &tolerance, &mesh, None, None, &mesh_to_world, entity);
+            let actual = ray_mesh_intersection(
+                &ray,
+                &tolerance,
+                &mesh,
+                cached.as_ref(),
+                None,
+                &mesh_to_world,
+                entity,
+            );
+            assert!(expected.is_some());
+            assert_eq!(expected, actual);
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use super::*;
+use std::collections::HashMap;
+
+/// Each entity's box in the index is enlarged by this fraction of its size on every side, so that
+/// small movements don't require the entity to be reinserted into the tree.
+const FAT_MARGIN: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy)]
+enum IndexNodeKind {
+    Leaf(Entity),
+    Branch { left: usize, right: usize },
+}
+
+#[derive(Debug)]
+struct IndexNode {
+    aabb: Aabb,
+    parent: Option<usize>,
+    kind: IndexNodeKind,
+}
+
+/// A scene wide spatial index over the world space bounding boxes of every `PickableMesh`, so that
+/// rays are only tested against the entities whose boxes they pass through. This is a dynamic
+/// bounding volume tree: entities are inserted and removed individually as they are added, moved
+/// or removed, rather than rebuilding the whole tree.
+#[derive(Debug, Default)]
+pub struct PickSceneIndex {
+    nodes: Vec<IndexNode>,
+    // Nodes in `nodes` that have been removed from the tree and can be reused
+    free_nodes: Vec<usize>,
+    root: Option<usize>,
+    leaves: HashMap<Entity, usize>,
+}
+
+impl PickSceneIndex {
+    /// Updates the bounding box of an entity, inserting it if it isn't in the index yet. Entities
+    /// are only reinserted when their box moves outside of the enlarged box stored in the tree.
+    pub(crate) fn update(&mut self, entity: Entity, aabb: Aabb) {
+        if let Some(leaf) = self.leaves.get(&entity) {
+            if self.nodes[*leaf].aabb.contains(&aabb) {
+                return;
+            }
+            self.remove(entity);
+        }
+        let margin = (aabb.max - aabb.min) * FAT_MARGIN;
+        self.insert(
+            entity,
+            Aabb {
+                min: aabb.min - margin,
+                max: aabb.max + margin,
+            },
+        );
+    }
+
+    pub(crate) fn remove(&mut self, entity: Entity) {
+        let leaf = match self.leaves.remove(&entity) {
+            Some(leaf) => leaf,
+            None => return,
+        };
+        self.free_nodes.push(leaf);
+        let parent = match self.nodes[leaf].parent {
+            Some(parent) => parent,
+            None => {
+                self.root = None;
+                return;
+            }
+        };
+        // The parent branch is no longer needed, the leaf's sibling takes its place
+        let sibling = match self.nodes[parent].kind {
+            IndexNodeKind::Branch { left, right } if left == leaf => right,
+            IndexNodeKind::Branch { left, .. } => left,
+            IndexNodeKind::Leaf(_) => unreachable!("Scene index leaf has a leaf as its parent"),
+        };
+        let grandparent = self.nodes[parent].parent;
+        self.nodes[sibling].parent = grandparent;
+        self.free_nodes.push(parent);
+        match grandparent {
+            Some(grandparent) => {
+                self.replace_child(grandparent, parent, sibling);
+                self.refit(Some(grandparent));
+            }
+            None => self.root = Some(sibling),
+        }
+    }
+
+    /// Returns every entity whose box is hit by the ray. Boxes are grown by the pick tolerance at
+    /// their far side, so lines and points just outside of them are still candidates.
+    pub(crate) fn ray_candidates(&self, ray: &Ray3d, tolerance: &PickTolerance) -> Vec<Entity> {
+        let mut candidates = Vec::new();
+        let root = match self.root {
+            Some(root) => root,
+            None => return candidates,
+        };
+        let inverse_direction = Vec3::one() / ray.direction();
+        let mut stack = vec![root];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            let half_diagonal = (node.aabb.max - node.aabb.min).length() / 2.0;
+            let far_distance = (node.aabb.center() - ray.origin()).length() + half_diagonal;
+            let margin = Vec3::splat(tolerance.at_distance(far_distance));
+            let aabb = Aabb {
+                min: node.aabb.min - margin,
+                max: node.aabb.max + margin,
+            };
+            if aabb.ray_entry_distance(ray, inverse_direction).is_none() {
+                continue;
+            }
+            match node.kind {
+                IndexNodeKind::Leaf(entity) => candidates.push(entity),
+                IndexNodeKind::Branch { left, right } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+        candidates
+    }
+
+    fn insert(&mut self, entity: Entity, aabb: Aabb) {
+        let leaf = self.allocate(IndexNode {
+            aabb,
+            parent: None,
+            kind: IndexNodeKind::Leaf(entity),
+        });
+        self.leaves.insert(entity, leaf);
+        let mut sibling = match self.root {
+            Some(root) => root,
+            None => {
+                self.root = Some(leaf);
+                return;
+            }
+        };
+
+        // Walk down the tree to find a sibling for the new leaf, at each branch choosing the child
+        // whose surface area would grow the least by including the new box.
+        while let IndexNodeKind::Branch { left, right } = self.nodes[sibling].kind {
+            let growth = |child: usize| {
+                let child_aabb = &self.nodes[child].aabb;
+                child_aabb.union(&aabb).surface_area() - child_aabb.surface_area()
+            };
+            sibling = if growth(left) <= growth(right) {
+                left
+            } else {
+                right
+            };
+        }
+
+        // Replace the sibling with a new branch holding both the sibling and the new leaf
+        let old_parent = self.nodes[sibling].parent;
+        let branch = self.allocate(IndexNode {
+            aabb: self.nodes[sibling].aabb.union(&aabb),
+            parent: old_parent,
+            kind: IndexNodeKind::Branch {
+                left: sibling,
+                right: leaf,
+            },
+        });
+        self.nodes[sibling].parent = Some(branch);
+        self.nodes[leaf].parent = Some(branch);
+        match old_parent {
+            Some(old_parent) => {
+                self.replace_child(old_parent, sibling, branch);
+                self.refit(Some(old_parent));
+            }
+            None => self.root = Some(branch),
+        }
+    }
+
+    fn allocate(&mut self, node: IndexNode) -> usize {
+        match self.free_nodes.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn replace_child(&mut self, parent: usize, old_child: usize, new_child: usize) {
+        if let IndexNodeKind::Branch { left, right } = &mut self.nodes[parent].kind {
+            if *left == old_child {
+                *left = new_child;
+            } else if *right == old_child {
+                *right = new_child;
+            }
+        }
+    }
+
+    /// Recomputes the boxes of a branch and all of its ancestors after one of its children changed
+    fn refit(&mut self, mut node: Option<usize>) {
+        while let Some(index) = node {
+            if let IndexNodeKind::Branch { left, right } = self.nodes[index].kind {
+                self.nodes[index].aabb = self.nodes[left].aabb.union(&self.nodes[right].aabb);
+            }
+            node = self.nodes[index].parent;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn unit_box(center: Vec3) -> Aabb {
+        Aabb {
+            min: center - Vec3::splat(0.5),
+            max: center + Vec3::splat(0.5),
+        }
+    }
+
+    fn candidates(index: &PickSceneIndex, origin: Vec3, direction: Vec3) -> HashSet<Entity> {
+        let ray = Ray3d::new(origin, direction);
+        index.ray_candidates(&ray, &PickTolerance::none()).into_iter().collect()
+    }
+
+    fn set(entities: &[Entity]) -> HashSet<Entity> {
+        entities.iter().copied().collect()
+    }
+
+    #[test]
+    fn update_remove_and_reinsert() {
+        let entities = test_entities(3);
+        let (a, b, c) = (entities[0], entities[1], entities[2]);
+        let mut index = PickSceneIndex::default();
+        let along_x = (Vec3::new(-10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let along_y = (Vec3::new(0.0, -10.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(candidates(&index, along_x.0, along_x.1).is_empty());
+
+        index.update(a, unit_box(Vec3::zero()));
+        index.update(b, unit_box(Vec3::new(5.0, 0.0, 0.0)));
+        index.update(c, unit_box(Vec3::new(0.0, 0.0, 5.0)));
+        assert_eq!(candidates(&index, along_x.0, along_x.1), set(&[a, b]));
+        assert_eq!(candidates(&index, along_y.0, along_y.1), set(&[a]));
+        // Rays pointing away from the boxes don't hit them
+        assert!(candidates(&index, along_x.0, -along_x.1).is_empty());
+
+        index.remove(a);
+        assert_eq!(candidates(&index, along_x.0, along_x.1), set(&[b]));
+        assert!(candidates(&index, along_y.0, along_y.1).is_empty());
+        // Removing an entity that isn't in the index does nothing
+        index.remove(a);
+
+        index.update(a, unit_box(Vec3::new(0.0, 5.0, 0.0)));
+        assert_eq!(candidates(&index, along_x.0, along_x.1), set(&[b]));
+        assert_eq!(candidates(&index, along_y.0, along_y.1), set(&[a]));
+
+        // Moving an entity far enough reinserts it at its new box
+        index.update(b, unit_box(Vec3::new(0.0, 0.0, -5.0)));
+        assert!(candidates(&index, along_x.0, along_x.1).is_empty());
+        let along_z = (Vec3::new(0.0, 0.0, -10.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(candidates(&index, along_z.0, along_z.1), set(&[b, c]));
+
+        index.remove(a);
+        index.remove(b);
+        index.remove(c);
+        assert!(candidates(&index, along_z.0, along_z.1).is_empty());
+    }
+}
@@ -0,0 +1,314 @@
+use super::*;
+use bevy::input::keyboard::KeyCode;
+use std::collections::HashSet;
+
+/// Actions that can be sent as events to modify the selection without clicking
+#[derive(Debug, Clone, PartialEq)]
+pub enum PickSelectionAction {
+    /// Select every entity with a `SelectablePickMesh`
+    SelectAll,
+    /// Select every unselected `SelectablePickMesh`, and deselect every selected one
+    Invert,
+    /// Deselect everything
+    Clear,
+    /// Replace the selection with the supplied entities
+    Set(Vec<Entity>),
+    /// Add the supplied entities to the selection
+    Add(Vec<Entity>),
+    /// Select the supplied entities that are unselected, and deselect those that are selected
+    Toggle(Vec<Entity>),
+    /// Select the next mesh under the cursor, behind the one that is currently selected, and show
+    /// the list of meshes under the cursor so one can be picked directly
+    SelectOther,
+}
+
+/// How a click modifies the current selection, determined by the modifier keys held at the time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ClickMode {
+    /// No modifier: the clicked mesh becomes the only selected mesh
+    Replace,
+    /// Shift: the clicked mesh is added to the selection
+    Add,
+    /// Ctrl: the clicked mesh is added if unselected, removed if selected
+    Toggle,
+}
+
+impl ClickMode {
+    pub(crate) fn from_keyboard(keyboard_input: &Input<KeyCode>) -> Self {
+        if keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl) {
+            ClickMode::Toggle
+        } else if keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift)
+        {
+            ClickMode::Add
+        } else {
+            ClickMode::Replace
+        }
+    }
+}
+
+/// The selectable meshes under the cursor where "select other" was last used, ordered front to
+/// back. Repeating `PickSelectionAction::SelectOther` cycles the selection through them, and they
+/// are listed next to the cursor so one can be clicked on directly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectOtherCandidates {
+    position: Vec2,
+    candidates: Vec<Entity>,
+    current: Option<usize>,
+}
+
+impl SelectOtherCandidates {
+    /// The cursor position the candidates were picked at
+    pub fn position(&self) -> Vec2 {
+        self.position
+    }
+    pub fn candidates(&self) -> &[Entity] {
+        &self.candidates
+    }
+    /// The candidate that was selected, if any
+    pub fn current(&self) -> Option<Entity> {
+        self.current.map(|index| self.candidates[index])
+    }
+}
+
+/// Holds the set of selected entities. `selected_previous` is the selection prior to the most
+/// recent change, so systems can tell what was added or removed.
+#[derive(Default)]
+pub struct PickSelectionState {
+    selected_next: HashSet<Entity>,
+    selected_previous: HashSet<Entity>,
+    select_other: Option<SelectOtherCandidates>,
+    // Whether the select other candidates are listed next to the cursor
+    select_other_list_open: bool,
+    action_event_reader: EventReader<PickSelectionAction>,
+}
+
+impl PickSelectionState {
+    pub fn selected(&self) -> &HashSet<Entity> {
+        &self.selected_next
+    }
+    pub fn selected_previous(&self) -> &HashSet<Entity> {
+        &self.selected_previous
+    }
+    pub fn is_selected(&self, entity: Entity) -> bool {
+        self.selected_next.contains(&entity)
+    }
+    /// The meshes the last `SelectOther` action could have selected
+    pub fn select_other(&self) -> Option<&SelectOtherCandidates> {
+        self.select_other.as_ref()
+    }
+    /// The candidates listed next to the cursor, while the list is open
+    pub fn select_other_list(&self) -> Option<&SelectOtherCandidates> {
+        self.select_other
+            .as_ref()
+            .filter(|_| self.select_other_list_open)
+    }
+}
+
+/// Given the current pick list, checks for a user click and if detected, updates the selected
+/// meshes in the `PickSelectionState` resource. A plain click replaces the selection with the
+/// nearest mesh, or clears it when clicking on empty space. Shift+click adds to the selection, and
+/// Ctrl+click toggles the clicked mesh. `PickSelectionAction` events are also handled here.
+///
+/// `SelectOther` selects the next mesh under the cursor, cycling from front to back through
+/// everything that was hit, and opens the list of candidates if there is more than one. Clicking on
+/// a row of the list selects its candidate, and any click closes the list. Cycling is deliberately
+/// not done by clicking repeatedly at the same spot, so double clicks keep the nearest mesh.
+///
+/// Clicks are told apart from drags by `pick_events`, a press that moved further than
+/// `DRAG_THRESHOLD` is a drag and is handled by `drag_select` instead, even if the cursor came back
+/// before the button was released.
+pub(crate) fn select_mesh(
+    // Resources
+    pick_state: Res<PickState>,
+    event_state: Res<PickEventState>,
+    mut selection_state: ResMut<PickSelectionState>,
+    list_params: Res<SelectOtherListParams>,
+    keyboard_input: Res<Input<KeyCode>>,
+    selection_actions: Res<Events<PickSelectionAction>>,
+    // Queries
+    mut query: Query<(&mut SelectablePickMesh, Entity)>,
+) {
+    let actions: Vec<PickSelectionAction> = selection_state
+        .action_event_reader
+        .iter(&selection_actions)
+        .cloned()
+        .collect();
+    let clicked = event_state.clicked();
+    if actions.is_empty() && !clicked {
+        return;
+    }
+
+    let mut selection = selection_state.selected_next.clone();
+    let mut select_other = false;
+
+    for action in actions {
+        match action {
+            PickSelectionAction::SelectAll => {
+                for (_selectable, entity) in &mut query.iter() {
+                    selection.insert(entity);
+                }
+            }
+            PickSelectionAction::Invert => {
+                let mut inverted = HashSet::new();
+                for (_selectable, entity) in &mut query.iter() {
+                    if !selection.contains(&entity) {
+                        inverted.insert(entity);
+                    }
+                }
+                selection = inverted;
+            }
+            PickSelectionAction::Clear => selection.clear(),
+            PickSelectionAction::Set(entities) => {
+                selection = entities.into_iter().collect();
+            }
+            PickSelectionAction::Add(entities) => {
+                selection.extend(entities);
+            }
+            PickSelectionAction::Toggle(entities) => {
+                for entity in entities {
+                    if !selection.remove(&entity) {
+                        selection.insert(entity);
+                    }
+                }
+            }
+            PickSelectionAction::SelectOther => select_other = true,
+        }
+    }
+
+    // Clicks on a row of the select other list select its candidate, instead of the mesh under it
+    let list_row = match (selection_state.select_other_list(), pick_state.cursor_position) {
+        (Some(list), Some(cursor)) if clicked => list_row_at(list, cursor),
+        _ => None,
+    };
+    if clicked {
+        selection_state.select_other_list_open = false;
+    }
+
+    let click_mode = ClickMode::from_keyboard(&keyboard_input);
+    if let Some(row) = list_row {
+        if let Some(list) = &mut selection_state.select_other {
+            selection.clear();
+            selection.insert(list.candidates[row]);
+            list.current = Some(row);
+        }
+    } else if select_other {
+        if let Some(cursor) = pick_state.cursor_position {
+            let candidates: Vec<Entity> = pick_state
+                .ordered_pick_list
+                .iter()
+                .map(|pick| pick.entity)
+                .filter(|entity| query.get::<SelectablePickMesh>(*entity).is_ok())
+                .collect();
+            let next = select_other_next(&candidates, &selection);
+            selection.clear();
+            selection.extend(next.map(|index| candidates[index]));
+            selection_state.select_other_list_open =
+                list_params.enabled() && candidates.len() > 1;
+            selection_state.select_other = Some(SelectOtherCandidates {
+                position: cursor,
+                candidates,
+                current: next,
+            });
+        }
+    } else if clicked {
+        // Only the nearest mesh under the cursor can be selected, anything behind it is occluded.
+        // If the nearest mesh isn't selectable, the click is treated as a click on empty space,
+        // which clears the selection for plain clicks, and leaves it untouched for modified clicks.
+        let nearest = pick_state
+            .ordered_pick_list
+            .first()
+            .map(|pick| pick.entity)
+            .filter(|entity| query.get::<SelectablePickMesh>(*entity).is_ok());
+
+        match click_mode {
+            ClickMode::Replace => {
+                selection.clear();
+                selection.extend(nearest);
+            }
+            ClickMode::Add => selection.extend(nearest),
+            ClickMode::Toggle => {
+                if let Some(entity) = nearest {
+                    if !selection.remove(&entity) {
+                        selection.insert(entity);
+                    }
+                }
+            }
+        }
+    }
+
+    // Drop any entities that have been despawned or are no longer selectable
+    selection.retain(|entity| query.get::<SelectablePickMesh>(*entity).is_ok());
+
+    if selection == selection_state.selected_next {
+        return;
+    }
+    selection_state.selected_previous =
+        std::mem::replace(&mut selection_state.selected_next, selection);
+
+    // Only write to the components whose state actually changed, so `Changed<SelectablePickMesh>`
+    // queries in user systems only see real selection changes. Highlighting follows the selection
+    // through the `PickInteraction` that `update_interactions` derives from it.
+    for (mut selectable, entity) in &mut query.iter() {
+        let selected = selection_state.selected_next.contains(&entity);
+        if selectable.selected != selected {
+            selectable.selected = selected;
+        }
+    }
+}
+
+/// Finds the index of the candidate that `SelectOther` selects. If the selection is a single
+/// candidate, the candidate behind it is selected, wrapping back around to the front. Otherwise the
+/// front candidate is selected.
+fn select_other_next(candidates: &[Entity], selection: &HashSet<Entity>) -> Option<usize> {
+    if candidates.is_empty() {
+        return None;
+    }
+    let current = match selection.len() {
+        1 => selection
+            .iter()
+            .next()
+            .and_then(|entity| candidates.iter().position(|candidate| candidate == entity)),
+        _ => None,
+    };
+    match current {
+        Some(index) => Some((index + 1) % candidates.len()),
+        None => Some(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selection(entities: &[Entity]) -> HashSet<Entity> {
+        entities.iter().copied().collect()
+    }
+
+    #[test]
+    fn select_other_cycles_front_to_back() {
+        let entities = test_entities(4);
+        let candidates = &entities[..3];
+        // Nothing selected yet, the front candidate is selected
+        assert_eq!(select_other_next(candidates, &selection(&[])), Some(0));
+        // Each step selects the candidate behind the current one, wrapping around to the front
+        assert_eq!(select_other_next(candidates, &selection(&[entities[0]])), Some(1));
+        assert_eq!(select_other_next(candidates, &selection(&[entities[1]])), Some(2));
+        assert_eq!(select_other_next(candidates, &selection(&[entities[2]])), Some(0));
+    }
+
+    #[test]
+    fn select_other_starts_at_front_without_a_single_candidate_selected() {
+        let entities = test_entities(4);
+        let candidates = &entities[..3];
+        let not_a_candidate = selection(&[entities[3]]);
+        assert_eq!(select_other_next(candidates, &not_a_candidate), Some(0));
+        let several = selection(&[entities[1], entities[2]]);
+        assert_eq!(select_other_next(candidates, &several), Some(0));
+    }
+
+    #[test]
+    fn select_other_without_candidates() {
+        let entities = test_entities(1);
+        assert_eq!(select_other_next(&[], &selection(&entities)), None);
+    }
+}
@@ -0,0 +1,177 @@
+use super::*;
+
+/// Size in pixels of each row of the select other list
+const LIST_ROW_WIDTH: f32 = 160.0;
+const LIST_ROW_HEIGHT: f32 = 20.0;
+/// Horizontal distance in pixels from the cursor to the list, so the list doesn't cover the meshes
+/// it lists
+const LIST_OFFSET: f32 = 12.0;
+
+/// Configures the list of candidates shown by `PickSelectionAction::SelectOther`. The rows are UI
+/// nodes, like the drag selection rectangle, so they are only visible with a UI camera. The plugin
+/// doesn't load any assets, so rows are only labelled once a font has been set. Labelled or not,
+/// hovering a row hovers its candidate, so the candidate is highlighted like a mesh under the
+/// cursor.
+#[derive(Debug)]
+pub struct SelectOtherListParams {
+    enabled: bool,
+    font: Option<Handle<Font>>,
+    row_color: Color,
+    current_row_color: Color,
+    text_color: Color,
+}
+
+impl SelectOtherListParams {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    /// Sets the font used to label each row with its entity
+    pub fn set_font(&mut self, font: Handle<Font>) {
+        self.font = Some(font);
+    }
+}
+
+impl Default for SelectOtherListParams {
+    fn default() -> Self {
+        SelectOtherListParams {
+            enabled: true,
+            font: None,
+            row_color: Color::rgba(0.2, 0.2, 0.2, 0.8),
+            current_row_color: Color::rgba(0.3, 0.5, 0.8, 0.8),
+            text_color: Color::rgb(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+/// Tracks the UI nodes drawing the select other list
+#[derive(Debug, Default)]
+pub(crate) struct SelectOtherListState {
+    // The candidates the list was last drawn for
+    shown: Option<SelectOtherCandidates>,
+    nodes: Vec<Entity>,
+    row_material: Handle<ColorMaterial>,
+    current_row_material: Handle<ColorMaterial>,
+}
+
+/// Creates the materials of the select other list rows
+pub(crate) fn select_other_list_init(
+    // Resources
+    params: Res<SelectOtherListParams>,
+    mut list_state: ResMut<SelectOtherListState>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+    list_state.row_material = color_materials.add(params.row_color.into());
+    list_state.current_row_material = color_materials.add(params.current_row_color.into());
+}
+
+/// Redraws the select other list when it is opened or closed, or when its current candidate
+/// changes. The list hangs down to the right of where select other was used, front candidate first.
+pub(crate) fn update_select_other_list(
+    // Commands
+    mut commands: Commands,
+    // Resources
+    selection_state: Res<PickSelectionState>,
+    params: Res<SelectOtherListParams>,
+    mut list_state: ResMut<SelectOtherListState>,
+) {
+    let list = selection_state.select_other_list();
+    if list == list_state.shown.as_ref() {
+        return;
+    }
+    for node in list_state.nodes.drain(..) {
+        commands.despawn(node);
+    }
+    list_state.shown = list.cloned();
+    let list = match list {
+        Some(list) => list,
+        None => return,
+    };
+
+    for (row, candidate) in list.candidates().iter().enumerate() {
+        let min = list_row_min(list, row);
+        let material = if list.current() == Some(*candidate) {
+            list_state.current_row_material
+        } else {
+            list_state.row_material
+        };
+        let node = commands
+            .spawn(NodeComponents {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        left: Val::Px(min.x()),
+                        bottom: Val::Px(min.y()),
+                        ..Default::default()
+                    },
+                    size: Size::new(Val::Px(LIST_ROW_WIDTH), Val::Px(LIST_ROW_HEIGHT)),
+                    ..Default::default()
+                },
+                material,
+                draw: Draw {
+                    is_transparent: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .current_entity();
+        list_state.nodes.extend(node);
+
+        if let Some(font) = params.font {
+            let label = commands
+                .spawn(TextComponents {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        position: Rect {
+                            left: Val::Px(min.x() + 4.0),
+                            bottom: Val::Px(min.y() + 2.0),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
+                    text: Text {
+                        value: format!("{}: {:?}", row + 1, candidate),
+                        font,
+                        style: TextStyle {
+                            font_size: LIST_ROW_HEIGHT - 4.0,
+                            color: params.text_color,
+                        },
+                    },
+                    ..Default::default()
+                })
+                .current_entity();
+            list_state.nodes.extend(label);
+        }
+    }
+}
+
+/// The bottom left corner of a row of the list, in window pixels
+fn list_row_min(list: &SelectOtherCandidates, row: usize) -> Vec2 {
+    list.position() + Vec2::new(LIST_OFFSET, -LIST_ROW_HEIGHT * (row + 1) as f32)
+}
+
+/// Finds the row of the list under a position in window pixels
+pub(crate) fn list_row_at(list: &SelectOtherCandidates, position: Vec2) -> Option<usize> {
+    let offset = position - list.position();
+    if offset.x() < LIST_OFFSET || offset.x() > LIST_OFFSET + LIST_ROW_WIDTH || offset.y() > 0.0 {
+        return None;
+    }
+    let row = (-offset.y() / LIST_ROW_HEIGHT) as usize;
+    if row < list.candidates().len() {
+        Some(row)
+    } else {
+        None
+    }
+}
+
+/// The candidate in the row of the select other list under the cursor, if the list is open
+pub(crate) fn hovered_list_candidate(
+    selection_state: &PickSelectionState,
+    pick_state: &PickState,
+) -> Option<Entity> {
+    let list = selection_state.select_other_list()?;
+    let row = list_row_at(list, pick_state.cursor_position?)?;
+    list.candidates().get(row).copied()
+}